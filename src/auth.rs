@@ -1,22 +1,77 @@
+use std::time::{Duration, Instant};
+
 use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{error::DuError, retry::RetryPolicy};
+
+// Refresh the token this long before it actually expires so in-flight requests never carry a stale token
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+// Lifetime assumed when the identity server omits `expires_in`
+const DEFAULT_EXPIRES_IN: u64 = 3600;
 
 pub struct Authentication {
+  http: Client,
   client_id: String,
   client_secret: String,
   token_url: String,
+  token: Mutex<Option<CachedToken>>,
+  retry: RetryPolicy,
+}
+
+struct CachedToken {
+  access_token: String,
+  expires_at: Instant,
+}
+
+impl CachedToken {
+  fn is_fresh(&self) -> bool {
+    Instant::now() + REFRESH_MARGIN < self.expires_at
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  access_token: String,
+  expires_in: Option<u64>,
 }
 
 impl Authentication {
-  pub fn new(client_id: &str, client_secret: &str, token_url: &str) -> Authentication {
+  pub fn new(http: Client, client_id: &str, client_secret: &str, token_url: &str) -> Authentication {
     Authentication {
+      http,
       client_id: client_id.to_string(),
       client_secret: client_secret.to_string(),
       token_url: token_url.to_string(),
+      token: Mutex::new(None),
+      retry: RetryPolicy::default(),
     }
   }
 
-  pub async fn get_bearer_token(&self) -> Option<String> {
-    let client = Client::new();
+  /// Replaces the default retry policy used for token requests.
+  pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Authentication {
+    self.retry = retry;
+    self
+  }
+
+  /// Returns the cached bearer token, fetching a new one when there is none yet or the current one is about to
+  /// expire.
+  pub async fn get_bearer_token(&self) -> Result<String, DuError> {
+    // Holding the lock across the fetch makes concurrent callers wait for a single refresh
+    let mut token = self.token.lock().await;
+    if let Some(cached) = token.as_ref().filter(|cached| cached.is_fresh()) {
+      return Ok(cached.access_token.clone());
+    }
+
+    let fresh = self.fetch_token().await?;
+    let access_token = fresh.access_token.clone();
+    *token = Some(fresh);
+    Ok(access_token)
+  }
+
+  async fn fetch_token(&self) -> Result<CachedToken, DuError> {
     let data = [
       ("client_id", &self.client_id),
       ("client_secret", &self.client_secret),
@@ -28,33 +83,33 @@ impl Authentication {
       ),
     ];
 
-    return match client.post(&self.token_url).form(&data).send().await {
-      Ok(response) => {
-        if response.status().is_success() {
-          match response.json::<serde_json::Value>().await {
-            Ok(token_data) => {
-              if let Some(access_token) = token_data.get("access_token") {
-                println!("Authenticated!\n");
-                Some(access_token.as_str().unwrap().to_string())
-              } else {
-                println!("Error: No access token received");
-                None
-              }
-            }
-            Err(e) => {
-              println!("Error parsing token data: {}", e);
-              None
-            }
-          }
-        } else {
-          println!("Error: HTTP request failed with status code {}", response.status());
-          None
-        }
-      }
-      Err(e) => {
-        println!("Error fetching token: {}", e);
-        None
-      }
-    };
+    // Failures are final once this policy gave up, so callers retrying API requests don't retry the token request too
+    let response = self
+      .retry
+      .execute(None, || async {
+        Ok(self.http.post(&self.token_url).form(&data).send().await?)
+      })
+      .await
+      .map_err(|err| match err {
+        DuError::Transport(_) | DuError::Timeout(_) => DuError::Auth(format!("token request failed: {}", err)),
+        err => err,
+      })?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+      return Err(DuError::Auth(format!(
+        "token request failed with status {}: {}",
+        status, body
+      )));
+    }
+
+    let token_data: TokenResponse =
+      serde_json::from_str(&body).map_err(|source| DuError::Deserialize { source, body })?;
+    eprintln!("Authenticated!\n");
+    let expires_in = Duration::from_secs(token_data.expires_in.unwrap_or(DEFAULT_EXPIRES_IN));
+    Ok(CachedToken {
+      access_token: token_data.access_token,
+      expires_at: Instant::now() + expires_in,
+    })
   }
 }
@@ -0,0 +1,159 @@
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::PathBuf,
+  sync::Mutex,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{digitize::DigitizationResult, error::DuError};
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+  document_id: String,
+  /// Seconds since the Unix epoch when the document was uploaded.
+  created_at: u64,
+  /// Set once digitization has succeeded and its result has been fetched.
+  digitization_result: Option<DigitizationResult>,
+}
+
+/// Local cache of digitized documents keyed by the SHA-256 of their bytes and MIME type, so identical files found in
+/// several places are uploaded only once.
+///
+/// Entries are JSON files in the cache folder and expire `ttl` after the upload, since the API only keeps digitized
+/// documents for a limited time.
+pub struct DigitizationCache {
+  directory: PathBuf,
+  ttl: Duration,
+  // Cache key of every document ID looked up or stored during this run
+  keys: Mutex<HashMap<String, String>>,
+}
+
+impl DigitizationCache {
+  pub fn new(directory: PathBuf, ttl: Duration) -> DigitizationCache {
+    DigitizationCache {
+      directory,
+      ttl,
+      keys: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Cache key of a file with `contents` uploaded as `mime_type`.
+  pub fn key(contents: &[u8], mime_type: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(contents);
+    hasher.update([0]);
+    hasher.update(mime_type.as_bytes());
+    hex::encode(hasher.finalize())
+  }
+
+  /// Returns the document ID stored under `key` if it has not expired.
+  pub fn document_id(&self, key: &str) -> Option<String> {
+    let entry = self.read(key)?;
+    self.remember(&entry.document_id, key);
+    Some(entry.document_id)
+  }
+
+  /// Returns the digitization result stored for `document_id`, if the document was found in or added to the cache
+  /// during this run and its result has been fetched.
+  pub fn digitization_result(&self, document_id: &str) -> Option<DigitizationResult> {
+    let key = self.key_of(document_id)?;
+    self.read(&key)?.digitization_result
+  }
+
+  /// Stores a freshly uploaded document under `key`.
+  pub fn insert_document_id(&self, key: &str, document_id: &str) -> Result<(), DuError> {
+    self.remember(document_id, key);
+    self.write(
+      key,
+      &CacheEntry {
+        document_id: document_id.to_string(),
+        created_at: now(),
+        digitization_result: None,
+      },
+    )
+  }
+
+  /// Adds the digitization result of `document_id` to its cache entry, if it has one.
+  pub fn insert_digitization_result(
+    &self,
+    document_id: &str,
+    digitization_result: &DigitizationResult,
+  ) -> Result<(), DuError> {
+    let Some(key) = self.key_of(document_id) else {
+      return Ok(());
+    };
+    let Some(mut entry) = self.read(&key) else {
+      return Ok(());
+    };
+    entry.digitization_result = Some(digitization_result.clone());
+    self.write(&key, &entry)
+  }
+
+  /// Removes the entry of `document_id`, e.g. because its digitization failed, so identical files are uploaded again.
+  pub fn remove(&self, document_id: &str) -> Result<(), DuError> {
+    // Documents resumed from an earlier run were never looked up by key in this one
+    let key = self.key_of(document_id).or_else(|| self.find_key(document_id));
+    let Some(key) = key else {
+      return Ok(());
+    };
+    match fs::remove_file(self.entry_path(&key)) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+      _ => Ok(()),
+    }
+  }
+
+  // Searches the cache folder for the entry of `document_id`
+  fn find_key(&self, document_id: &str) -> Option<String> {
+    fs::read_dir(&self.directory)
+      .ok()?
+      .flatten()
+      .filter_map(|file| Some(file.path().file_stem()?.to_string_lossy().to_string()))
+      .find(|key| self.read(key).is_some_and(|entry| entry.document_id == document_id))
+  }
+
+  // Reads the entry stored under `key`; expired and unreadable entries count as missing
+  fn read(&self, key: &str) -> Option<CacheEntry> {
+    let contents = fs::read_to_string(self.entry_path(key)).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+    (now().saturating_sub(entry.created_at) < self.ttl.as_secs()).then_some(entry)
+  }
+
+  fn write(&self, key: &str, entry: &CacheEntry) -> Result<(), DuError> {
+    fs::create_dir_all(&self.directory)?;
+    let json = serde_json::to_vec(entry).map_err(io::Error::from)?;
+    fs::write(self.entry_path(key), json)?;
+    Ok(())
+  }
+
+  fn entry_path(&self, key: &str) -> PathBuf {
+    self.directory.join(format!("{}.json", key))
+  }
+
+  fn remember(&self, document_id: &str, key: &str) {
+    self
+      .keys
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .insert(document_id.to_string(), key.to_string());
+  }
+
+  fn key_of(&self, document_id: &str) -> Option<String> {
+    self
+      .keys
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .get(document_id)
+      .cloned()
+  }
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs()
+}
@@ -1,15 +1,11 @@
-use reqwest::{
-  header::{AUTHORIZATION, CONTENT_TYPE},
-  Client,
-};
+use reqwest::{header::CONTENT_TYPE, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-pub struct Classify {
-  base_url: String,
-  project_id: String,
-  bearer_token: String,
-}
+use crate::{
+  client::{read_json, DuClient},
+  error::DuError,
+};
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -28,10 +24,10 @@ pub struct ClassificationResults {
 pub struct ClassificationResult {
   pub(crate) document_type_id: String,
   document_id: String,
-  confidence: f64,
+  pub(crate) confidence: f64,
   ocr_confidence: f64,
   reference: Reference,
-  document_bounds: DocumentBounds,
+  pub(crate) document_bounds: DocumentBounds,
   classifier_name: String,
 }
 
@@ -55,77 +51,103 @@ pub struct DocumentBounds {
   text_length: usize,
 }
 
-impl Classify {
-  pub fn new(base_url: &str, project_id: &str, bearer_token: &str) -> Classify {
-    Classify {
-      base_url: base_url.to_string(),
-      project_id: project_id.to_string(),
-      bearer_token: bearer_token.to_string(),
-    }
+impl DocumentBounds {
+  /// First and last page of the document, counted from 1.
+  pub fn pages(&self) -> (usize, usize) {
+    (self.start_page + 1, self.start_page + self.page_count.max(1))
   }
+}
 
-  pub async fn classify_document(
+impl DuClient {
+  pub async fn classify(
     &self,
     document_id: &str,
     classifier: &str,
     prompts: Option<serde_json::Value>,
-  ) -> Option<ClassificationResults> {
+  ) -> Result<ClassificationResults, DuError> {
     // Define the API endpoint for document classification
-    let api_url = format!(
-      "{}/{}/classifiers/{}/classification?api-version=1",
-      self.base_url, self.project_id, classifier
-    );
+    let api_url = self.project_url(&format!("classifiers/{}/classification", classifier));
 
     // Prepare request data
     let data = ClassificationData { document_id, prompts };
 
     // Prepare request
-    let client = Client::new();
-    let response = client
-      .post(&api_url)
-      .header(AUTHORIZATION, format!("Bearer {}", self.bearer_token))
-      .header(CONTENT_TYPE, "application/json")
-      .json(&data)
-      .send()
-      .await;
+    let response = self
+      .send(Method::POST, &api_url, |request| {
+        request.header(CONTENT_TYPE, "application/json").json(&data)
+      })
+      .await?;
 
     // Process response
-    match response {
-      Ok(response) => match response.status() {
-        reqwest::StatusCode::OK => {
-          println!("Document successfully classified!");
-          let classification_results: ClassificationResults = response.json().await.unwrap();
-          let mut document_type_id = None;
-          let mut classification_confidence = None;
-          for result in &classification_results.classification_results {
-            if result.document_id == document_id {
-              document_type_id = Some(result.document_type_id.as_str());
-              classification_confidence = Some(result.confidence);
-              break;
-            }
-          }
-          if let (Some(document_type_id), Some(classification_confidence)) =
-            (document_type_id, classification_confidence)
-          {
-            println!(
-              "Document Type ID: {}, Confidence: {}\n",
-              document_type_id, classification_confidence
-            );
-          } else {
-            println!("Document ID not found in classification results.");
-            return None;
-          }
-          Some(classification_results)
-        }
-        _ => {
-          println!("Error: {} - {}", response.status(), response.text().await.unwrap());
-          None
-        }
-      },
-      Err(e) => {
-        println!("An error occurred during classification: {}", e);
-        None
-      }
+    let classification_results: ClassificationResults = read_json(response).await?;
+    classification_results.check_document(document_id)?;
+    Ok(classification_results)
+  }
+}
+
+impl ClassificationResults {
+  // Rejects results of another document. No results at all are fine: the classifier found no known document type.
+  fn check_document(&self, document_id: &str) -> Result<(), DuError> {
+    match self
+      .classification_results
+      .iter()
+      .find(|result| result.document_id != document_id)
+    {
+      Some(result) => Err(DuError::UnexpectedResponse(format!(
+        "classification results of document {} returned for document {}",
+        result.document_id, document_id
+      ))),
+      None => Ok(()),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn results(document_ids: &[&str]) -> ClassificationResults {
+    let results: Vec<_> = document_ids
+      .iter()
+      .map(|document_id| {
+        json!({
+          "document_type_id": "invoices", "document_id": document_id, "confidence": 0.9, "ocr_confidence": 0.9,
+          "reference": { "text_start_index": 0, "text_length": 1, "tokens": [] },
+          "document_bounds": { "start_page": 0, "page_count": 1, "text_start_index": 0, "text_length": 1 },
+          "classifier_name": "ml"
+        })
+      })
+      .collect();
+    serde_json::from_value(json!({ "classification_results": results })).unwrap()
+  }
+
+  #[test]
+  fn accepts_results_without_a_document_type() {
+    assert!(results(&[]).check_document("doc").is_ok());
+  }
+
+  #[test]
+  fn accepts_results_of_the_document() {
+    assert!(results(&["doc", "doc"]).check_document("doc").is_ok());
+  }
+
+  #[test]
+  fn rejects_results_of_another_document() {
+    assert!(results(&["doc", "other"]).check_document("doc").is_err());
+  }
+
+  #[test]
+  fn pages_count_from_one() {
+    let bounds = |start_page, page_count| DocumentBounds {
+      start_page,
+      page_count,
+      text_start_index: 0,
+      text_length: 0,
+    };
+    assert_eq!(bounds(0, 2).pages(), (1, 2));
+    assert_eq!(bounds(2, 1).pages(), (3, 3));
+    assert_eq!(bounds(4, 0).pages(), (5, 5));
+  }
+}
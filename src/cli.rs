@@ -0,0 +1,443 @@
+use std::path::PathBuf;
+
+use clap::{builder::RangedU64ValueParser, value_parser, Arg, ArgAction, Command};
+
+use crate::{result_utils::OutputFormat, thresholds::LowConfidenceClassification};
+
+// Defines the command-line interface: one subcommand per stage plus the end-to-end `pipeline`
+pub fn build() -> Command {
+  Command::new("Document Processor")
+    .version("1.0")
+    .author("Your Name")
+    .about("Digitize, classify, extract and validate documents with UiPath Document Understanding")
+    .subcommand_required(true)
+    .arg_required_else_help(true)
+    .arg(
+      Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .help("Configuration file (default: rustydu.toml in the working directory, if present)")
+        .value_parser(value_parser!(PathBuf))
+        .global(true),
+    )
+    .arg(
+      Arg::new("env_file")
+        .long("env-file")
+        .value_name("FILE")
+        .help("Dotenv file with APP_ID, APP_SECRET, AUTH_URL, BASE_URL and PROJECT_ID (default: .env in the working directory, if present)")
+        .value_parser(value_parser!(PathBuf))
+        .global(true),
+    )
+    .arg(
+      Arg::new("project_id")
+        .long("project-id")
+        .value_name("PROJECT_ID")
+        .help("Project to use; overrides PROJECT_ID and the configuration file")
+        .global(true),
+    )
+    .arg(
+      Arg::new("no_cache")
+        .long("no-cache")
+        .help("Uploads every document for digitization, even if identical content was digitized recently")
+        .action(ArgAction::SetTrue)
+        .global(true),
+    )
+    .args(retry_args())
+    .subcommand(
+      Command::new("pipeline")
+        .about("Runs every document in a folder through digitization, classification, extraction and validation")
+        .arg(
+          Arg::new("folder")
+            .long("folder")
+            .value_name("FOLDER")
+            .help("Sets the folder path containing documents to process")
+            .value_parser(value_parser!(PathBuf))
+            .required(true),
+        )
+        .args(input_args())
+        .args(pipeline_args())
+        .arg(
+          Arg::new("fail_fast")
+            .long("fail-fast")
+            .help("Stops starting new documents after the first document fails")
+            .action(ArgAction::SetTrue),
+        ),
+    )
+    .subcommand(
+      Command::new("watch")
+        .about("Watches a folder and runs every new document through the pipeline, then moves it to processed/ or failed/")
+        .arg(
+          Arg::new("folder")
+            .long("folder")
+            .value_name("FOLDER")
+            .help("Sets the folder to watch for new documents")
+            .value_parser(value_parser!(PathBuf))
+            .required(true),
+        )
+        .arg(
+          Arg::new("poll_interval_secs")
+            .long("poll-interval-secs")
+            .value_name("SECS")
+            .help("How often the folder is scanned for new documents")
+            .value_parser(value_parser!(u64).range(1..))
+            .default_value("2"),
+        )
+        .arg(
+          Arg::new("settle_secs")
+            .long("settle-secs")
+            .value_name("SECS")
+            .help("How long a file must stay unchanged before it is processed, so half-written files are skipped")
+            .value_parser(value_parser!(u64))
+            .default_value("5"),
+        )
+        .args(input_args())
+        .args(pipeline_args()),
+    )
+    .subcommand(
+      Command::new("digitize")
+        .about("Digitizes a file and prints its document ID")
+        .arg(
+          Arg::new("file")
+            .value_name("FILE")
+            .help("Document to digitize")
+            .value_parser(value_parser!(PathBuf))
+            .required(true),
+        )
+        .arg(digitization_timeout_arg())
+        .arg(output_arg(
+          "Writes the digitization result (DOM and text) as JSON to this file",
+        )),
+    )
+    .subcommand(
+      Command::new("classify")
+        .about("Classifies a digitized document and prints the classification results as JSON")
+        .arg(document_id_arg())
+        .arg(
+          Arg::new("classifier")
+            .long("classifier")
+            .value_name("CLASSIFIER_ID")
+            .help("Classifier to use (default: the configured classifier, else ml-classification)"),
+        )
+        .arg(prompts_arg())
+        .arg(output_arg(
+          "Writes the classification results to this file instead of stdout",
+        )),
+    )
+    .subcommand(
+      Command::new("extract")
+        .about("Extracts a digitized document and prints the extraction results as JSON")
+        .arg(document_id_arg())
+        .arg(extractor_id_arg())
+        .arg(prompts_arg())
+        .arg(output_arg(
+          "Writes the extraction results to this file instead of stdout",
+        )),
+    )
+    .subcommand(
+      Command::new("validate")
+        .about("Starts a validation task in Action Center and waits for it to be completed")
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("classification")
+            .about(
+              "Validates classification results and prints the confirmed document type ID, or a line with the type \
+               and pages of each document when the file holds several",
+            )
+            .arg(document_id_arg())
+            .arg(
+              Arg::new("classifier")
+                .long("classifier")
+                .value_name("CLASSIFIER_ID")
+                .help("Classifier that produced the classification results (default: the configured classifier, else ml-classification)"),
+            )
+            .arg(input_arg("Classification results JSON, as written by `classify`")),
+        )
+        .subcommand(
+          Command::new("extraction")
+            .about("Validates extraction results and prints the validated results as JSON")
+            .arg(extractor_id_arg())
+            .arg(input_arg("Extraction results JSON, as written by `extract`"))
+            .arg(output_arg(
+              "Writes the validated results to this file instead of stdout",
+            )),
+        ),
+    )
+    .subcommand(
+      Command::new("discover")
+        .about("Lists projects, or the classifiers, document types and extractors of a project")
+        .arg(
+          Arg::new("project")
+            .long("project")
+            .value_name("PROJECT_ID")
+            .help("Project to describe; lists all projects when omitted"),
+        ),
+    )
+}
+
+// Options selecting the documents of the input folder
+fn input_args() -> Vec<Arg> {
+  vec![
+    Arg::new("recursive")
+      .long("recursive")
+      .help("Also processes documents in subfolders of the folder")
+      .action(ArgAction::SetTrue),
+    Arg::new("include")
+      .long("include")
+      .value_name("GLOB")
+      .help("Only processes files whose path relative to the folder matches; repeat to allow several patterns")
+      .action(ArgAction::Append),
+    Arg::new("exclude")
+      .long("exclude")
+      .value_name("GLOB")
+      .help("Skips files whose path relative to the folder matches; repeat to list several patterns")
+      .action(ArgAction::Append),
+    Arg::new("extension")
+      .long("extension")
+      .value_name("EXT")
+      .help("File extension to process; repeat to list several (default: png, jpe, jpg, jpeg, tiff, tif, bmp, pdf)")
+      .action(ArgAction::Append),
+  ]
+}
+
+// Options of the end-to-end pipeline
+fn pipeline_args() -> Vec<Arg> {
+  vec![
+    Arg::new("validate_classification")
+      .long("validate-classification")
+      .help("Enables classification validation")
+      .action(ArgAction::SetTrue),
+    Arg::new("validate_extraction")
+      .long("validate-extraction")
+      .help("Enables extraction validation")
+      .action(ArgAction::SetTrue),
+    Arg::new("generative_classification")
+      .long("generative-classification")
+      .help("Enables generative classification")
+      .action(ArgAction::SetTrue),
+    Arg::new("generative_extraction")
+      .long("generative-extraction")
+      .help("Enables generative extraction")
+      .action(ArgAction::SetTrue),
+    Arg::new("classifier")
+      .long("classifier")
+      .value_name("CLASSIFIER_ID")
+      .help(
+        "Classifier to use (default: generative_classifier with --generative-classification, else the configured classifier, else ml-classification)",
+      ),
+    Arg::new("extractor")
+      .long("extractor")
+      .value_name("DOCUMENT_TYPE=EXTRACTOR_ID")
+      .help("Extractor to use for a document type; repeat for several document types")
+      .value_parser(parse_extractor_mapping)
+      .action(ArgAction::Append),
+    Arg::new("classification_threshold")
+      .long("classification-threshold")
+      .value_name("CONFIDENCE")
+      .help("Lowest classification confidence a document type is extracted with without a closer look")
+      .value_parser(parse_confidence),
+    Arg::new("low_confidence_classification")
+      .long("low-confidence-classification")
+      .value_name("ACTION")
+      .help("What to do with documents classified below --classification-threshold: validate the classification, or copy them to the unclassified folder (default: unclassified)")
+      .value_parser(|action: &str| action.parse::<LowConfidenceClassification>()),
+    Arg::new("field_threshold")
+      .long("field-threshold")
+      .value_name("CONFIDENCE")
+      .help("Lowest confidence of an extracted value accepted as is; with --validate-extraction, only extractions with a value below its threshold are validated")
+      .value_parser(parse_confidence),
+    Arg::new("ocr_threshold")
+      .long("ocr-threshold")
+      .value_name("CONFIDENCE")
+      .help("Lowest OCR confidence of an extracted value accepted as is")
+      .value_parser(parse_confidence),
+    Arg::new("threshold")
+      .long("threshold")
+      .value_name("DOCUMENT_TYPE[:FIELD]=CONFIDENCE")
+      .help("Field confidence threshold of a document type, or of one of its fields by ID or name; repeat for several")
+      .value_parser(parse_threshold)
+      .action(ArgAction::Append),
+    Arg::new("output_dir")
+      .long("output-dir")
+      .value_name("DIR")
+      .help("Folder the results are written to (default: output_results)")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("prompts_dir")
+      .long("prompts-dir")
+      .value_name("DIR")
+      .help("Folder with the <document type>_prompts.json files for generative classifiers and extractors (default: generative_prompts)")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("multi_value_separator")
+      .long("multi-value-separator")
+      .value_name("SEPARATOR")
+      .help("Joins the values of multi-valued fields and table cells in the CSV output (default: \"; \")"),
+    Arg::new("format")
+      .long("format")
+      .value_name("FORMAT")
+      .help("Format the results are written in: csv, json, ndjson (appended to results.ndjson) or xlsx (one results.xlsx per batch); repeat for several formats (default: csv)")
+      .value_parser(|format: &str| format.parse::<OutputFormat>())
+      .action(ArgAction::Append),
+    Arg::new("save_ocr")
+      .long("save-ocr")
+      .help("Saves the digitization DOM (<name>_dom.json) and text (<name>.txt) to the output folder")
+      .action(ArgAction::SetTrue),
+    Arg::new("concurrency")
+      .long("concurrency")
+      .value_name("N")
+      .help("Number of documents processed in parallel")
+      .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+      .default_value("1"),
+    digitization_timeout_arg(),
+  ]
+}
+
+// Retry options, accepted before or after any subcommand
+fn retry_args() -> Vec<Arg> {
+  vec![
+    Arg::new("max_attempts")
+      .long("max-attempts")
+      .value_name("N")
+      .help("Maximum number of attempts per API request, including the first one")
+      .value_parser(value_parser!(u32).range(1..))
+      .global(true),
+    Arg::new("retry_base_delay_ms")
+      .long("retry-base-delay-ms")
+      .value_name("MS")
+      .help("Delay before the first retry; doubled on every further retry")
+      .value_parser(value_parser!(u64))
+      .global(true),
+    Arg::new("retry_max_delay_ms")
+      .long("retry-max-delay-ms")
+      .value_name("MS")
+      .help("Upper bound for the exponential retry delay")
+      .value_parser(value_parser!(u64))
+      .global(true),
+    Arg::new("retry_jitter_ms")
+      .long("retry-jitter-ms")
+      .value_name("MS")
+      .help("Maximum random delay added to every retry")
+      .value_parser(value_parser!(u64))
+      .global(true),
+    Arg::new("retry_status")
+      .long("retry-status")
+      .value_name("CODE")
+      .help("HTTP status code to retry; repeat to list several (default: 408, 429, 500, 502, 503, 504)")
+      .value_parser(value_parser!(u16).range(100..=599))
+      .action(ArgAction::Append)
+      .global(true),
+  ]
+}
+
+fn digitization_timeout_arg() -> Arg {
+  Arg::new("digitization_timeout_secs")
+    .long("digitization-timeout-secs")
+    .value_name("SECS")
+    .help("How long to wait for a document to finish digitizing")
+    .value_parser(value_parser!(u64))
+    .default_value("300")
+}
+
+fn document_id_arg() -> Arg {
+  Arg::new("document_id")
+    .long("document-id")
+    .value_name("DOCUMENT_ID")
+    .help("Document ID returned by `digitize`")
+    .required(true)
+}
+
+fn extractor_id_arg() -> Arg {
+  Arg::new("extractor")
+    .long("extractor")
+    .value_name("EXTRACTOR_ID")
+    .help("Extractor to use")
+    .required(true)
+}
+
+fn prompts_arg() -> Arg {
+  Arg::new("prompts")
+    .long("prompts")
+    .value_name("FILE")
+    .help("JSON file with prompts for generative classifiers and extractors")
+    .value_parser(value_parser!(PathBuf))
+}
+
+fn input_arg(help: &'static str) -> Arg {
+  Arg::new("input")
+    .long("input")
+    .value_name("FILE")
+    .help(help)
+    .value_parser(value_parser!(PathBuf))
+    .required(true)
+}
+
+fn output_arg(help: &'static str) -> Arg {
+  Arg::new("output")
+    .long("output")
+    .value_name("FILE")
+    .help(help)
+    .value_parser(value_parser!(PathBuf))
+}
+
+// Parses a `DOCUMENT_TYPE=EXTRACTOR_ID` mapping given with `--extractor`
+fn parse_extractor_mapping(value: &str) -> Result<(String, String), String> {
+  match value.split_once('=') {
+    Some((document_type_id, extractor_id)) if !document_type_id.is_empty() && !extractor_id.is_empty() => {
+      Ok((document_type_id.to_string(), extractor_id.to_string()))
+    }
+    _ => Err(format!("expected DOCUMENT_TYPE=EXTRACTOR_ID, got '{}'", value)),
+  }
+}
+
+// Parses a confidence between 0 and 1
+fn parse_confidence(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(confidence) if (0.0..=1.0).contains(&confidence) => Ok(confidence),
+    _ => Err(format!("expected a confidence between 0 and 1, got '{}'", value)),
+  }
+}
+
+// Parses DOCUMENT_TYPE=CONFIDENCE or DOCUMENT_TYPE:FIELD=CONFIDENCE
+fn parse_threshold(value: &str) -> Result<(String, Option<String>, f32), String> {
+  let Some((target, confidence)) = value.rsplit_once('=') else {
+    return Err(format!("expected DOCUMENT_TYPE[:FIELD]=CONFIDENCE, got '{}'", value));
+  };
+  let (document_type_id, field) = match target.split_once(':') {
+    Some((document_type_id, field)) => (document_type_id, Some(field.to_string())),
+    None => (target, None),
+  };
+  if document_type_id.is_empty() || field.as_deref() == Some("") {
+    return Err(format!("expected DOCUMENT_TYPE[:FIELD]=CONFIDENCE, got '{}'", value));
+  }
+  Ok((document_type_id.to_string(), field, parse_confidence(confidence)?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_document_type_thresholds() {
+    assert_eq!(parse_threshold("invoices=0.8"), Ok(("invoices".to_string(), None, 0.8)));
+  }
+
+  #[test]
+  fn parses_field_thresholds() {
+    assert_eq!(
+      parse_threshold("invoices:Total=0.95"),
+      Ok(("invoices".to_string(), Some("Total".to_string()), 0.95))
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_thresholds() {
+    for value in [
+      "invoices",
+      "=0.8",
+      "invoices:=0.8",
+      "invoices=high",
+      "invoices=1.5",
+      "invoices=-0.1",
+    ] {
+      assert!(parse_threshold(value).is_err(), "{}", value);
+    }
+  }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use reqwest::{header::AUTHORIZATION, Client, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+
+use crate::{
+  auth::Authentication, cache::DigitizationCache, error::DuError, retry::RetryPolicy, validate::ValidationSettings,
+};
+
+/// Client for the Document Understanding framework API.
+///
+/// A single `reqwest::Client` is shared by every call so connection pools and TLS sessions are reused across
+/// digitization, classification, extraction and validation requests. Cloning is cheap and clones share the same pool
+/// and token cache.
+#[derive(Clone)]
+pub struct DuClient {
+  http: Client,
+  base_url: String,
+  // Missing for clients that only list projects
+  project_id: Option<String>,
+  auth: Arc<Authentication>,
+  retry: RetryPolicy,
+  // Prefix of retry messages, identifying what the requests are for
+  label: Option<String>,
+  pub(crate) validation: ValidationSettings,
+  pub(crate) digitization_cache: Option<Arc<DigitizationCache>>,
+}
+
+impl DuClient {
+  pub fn new(http: Client, base_url: &str, auth: Arc<Authentication>) -> DuClient {
+    DuClient {
+      http,
+      base_url: base_url.trim_end_matches('/').to_string(),
+      project_id: None,
+      auth,
+      retry: RetryPolicy::default(),
+      label: None,
+      validation: ValidationSettings::default(),
+      digitization_cache: None,
+    }
+  }
+
+  /// Sends project requests, such as digitization and extraction, to `project_id`.
+  pub fn with_project(mut self, project_id: &str) -> DuClient {
+    self.project_id = Some(project_id.to_string());
+    self
+  }
+
+  /// Replaces the default retry policy used for every request this client sends.
+  pub fn with_retry_policy(mut self, retry: RetryPolicy) -> DuClient {
+    self.retry = retry;
+    self
+  }
+
+  /// Labels the retry messages of requests sent by this client, e.g. with the path of the document they are for.
+  pub fn with_label(mut self, label: &str) -> DuClient {
+    self.label = Some(label.to_string());
+    self
+  }
+
+  /// Replaces the Action Center catalog, folder and storage bucket used for validation tasks.
+  pub fn with_validation_settings(mut self, validation: ValidationSettings) -> DuClient {
+    self.validation = validation;
+    self
+  }
+
+  /// Reuses documents from `cache` when identical content is digitized again, instead of uploading it.
+  pub fn with_digitization_cache(mut self, cache: DigitizationCache) -> DuClient {
+    self.digitization_cache = Some(Arc::new(cache));
+    self
+  }
+
+  // Builds the URL of an endpoint relative to the base URL; an empty path addresses the project list
+  pub(crate) fn api_url(&self, path: &str) -> String {
+    if path.is_empty() {
+      format!("{}?api-version=1", self.base_url)
+    } else {
+      format!("{}/{}?api-version=1", self.base_url, path)
+    }
+  }
+
+  // Builds the URL of an endpoint of the client's project, e.g. `digitization/start`. Every caller of a project
+  // endpoint builds its client with a project, so a missing one is a bug.
+  pub(crate) fn project_url(&self, path: &str) -> String {
+    let project_id = self.project_id.as_deref().expect("client has no project");
+    self.api_url(&format!("{}/{}", project_id, path))
+  }
+
+  // Sends a request carrying the current bearer token, retrying according to the retry policy. `build` is called
+  // once per attempt to add headers and body to the request.
+  pub(crate) async fn send(
+    &self,
+    method: Method,
+    url: &str,
+    build: impl Fn(RequestBuilder) -> RequestBuilder,
+  ) -> Result<Response, DuError> {
+    self
+      .retry
+      .execute(self.label.as_deref(), || async {
+        let bearer_token = self.auth.get_bearer_token().await?;
+        let request = self
+          .http
+          .request(method.clone(), url)
+          .header(AUTHORIZATION, format!("Bearer {}", bearer_token));
+        Ok(build(request).send().await?)
+      })
+      .await
+  }
+}
+
+// Reads a response body as JSON, turning non-success statuses into `DuError::Http` so the body is never lost
+pub(crate) async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, DuError> {
+  let status = response.status();
+  let body = response.text().await?;
+  if !status.is_success() {
+    return Err(DuError::Http { status, body });
+  }
+  serde_json::from_str(&body).map_err(|source| DuError::Deserialize { source, body })
+}
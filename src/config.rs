@@ -0,0 +1,418 @@
+use std::{
+  collections::HashMap,
+  env, fs,
+  path::{Path, PathBuf},
+  str::FromStr,
+  time::Duration,
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+  documents::DEFAULT_EXTENSIONS,
+  error::DuError,
+  result_utils::OutputFormat,
+  thresholds::{LowConfidenceClassification, Thresholds},
+  validate::ValidationSettings,
+};
+
+/// Configuration file read from the working directory when `--config` is not given.
+pub const DEFAULT_CONFIG_FILE: &str = "rustydu.toml";
+
+// How long a digitized document is reused when no TTL is configured
+const DEFAULT_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Dotenv file read from the working directory when `--env-file` is not given.
+pub const DEFAULT_DOTENV_FILE: &str = ".env";
+
+// Layout of `rustydu.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+  credentials: CredentialsSection,
+  project: ProjectSection,
+  classification: ClassificationSection,
+  /// Extractor ID per document type ID.
+  extractors: HashMap<String, String>,
+  validation: ValidationSection,
+  thresholds: Thresholds,
+  input: InputSection,
+  output: OutputSection,
+  cache: CacheSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CredentialsSection {
+  #[serde(deserialize_with = "non_empty")]
+  app_id: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  app_secret: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  auth_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProjectSection {
+  #[serde(deserialize_with = "non_empty")]
+  base_url: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ClassificationSection {
+  #[serde(deserialize_with = "non_empty")]
+  classifier: Option<String>,
+  low_confidence: Option<LowConfidenceClassification>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ValidationSection {
+  #[serde(deserialize_with = "non_empty")]
+  action_catalog: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  action_folder: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  action_priority: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  storage_bucket: Option<String>,
+  #[serde(deserialize_with = "non_empty")]
+  storage_bucket_directory: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct InputSection {
+  recursive: Option<bool>,
+  include: Vec<String>,
+  exclude: Vec<String>,
+  extensions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputSection {
+  #[serde(deserialize_with = "non_empty")]
+  directory: Option<PathBuf>,
+  #[serde(deserialize_with = "non_empty")]
+  prompts_directory: Option<PathBuf>,
+  save_ocr: Option<bool>,
+  #[serde(deserialize_with = "non_empty")]
+  multi_value_separator: Option<String>,
+  formats: Option<Vec<OutputFormat>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheSection {
+  enabled: Option<bool>,
+  #[serde(deserialize_with = "non_empty")]
+  directory: Option<PathBuf>,
+  ttl_secs: Option<u64>,
+}
+
+/// Credentials and endpoints needed to talk to the API.
+pub struct Connection {
+  pub app_id: String,
+  pub app_secret: String,
+  pub auth_url: String,
+  pub base_url: String,
+  /// Only resolved when the caller asked for it.
+  pub project_id: Option<String>,
+}
+
+/// Settings resolved from, in order of precedence, the process environment, `.env` and `rustydu.toml`.
+///
+/// Command-line options take precedence over all of them and are applied on top by the caller.
+pub struct Config {
+  pub app_id: Option<String>,
+  pub app_secret: Option<String>,
+  pub auth_url: Option<String>,
+  pub base_url: Option<String>,
+  pub project_id: Option<String>,
+  pub classifier: Option<String>,
+  /// What to do with documents classified below the classification confidence threshold.
+  pub low_confidence_classification: LowConfidenceClassification,
+  pub extractors: HashMap<String, String>,
+  pub validation: ValidationSettings,
+  pub thresholds: Thresholds,
+  /// Descend into subfolders of the input folder.
+  pub recursive: bool,
+  /// Glob patterns, relative to the input folder, of the files to process; empty means all.
+  pub include: Vec<String>,
+  pub exclude: Vec<String>,
+  /// File extensions of the documents to process.
+  pub extensions: Vec<String>,
+  pub output_directory: PathBuf,
+  pub prompts_directory: PathBuf,
+  pub save_ocr: bool,
+  /// Joins the values of multi-valued fields and table cells in CSV output.
+  pub multi_value_separator: String,
+  /// Formats the results are written in.
+  pub formats: Vec<OutputFormat>,
+  /// Reuse documents digitized earlier; see [`crate::cache::DigitizationCache`].
+  pub cache_enabled: bool,
+  pub cache_directory: PathBuf,
+  pub cache_ttl: Duration,
+  // Where the configuration and dotenv files were read from, for error messages
+  source: PathBuf,
+  dotenv_source: PathBuf,
+}
+
+impl Config {
+  /// Loads the configuration file at `path`, or `rustydu.toml` if it exists when no path is given, and layers the
+  /// dotenv file at `env_file` (or `.env` if it exists) and the process environment over it.
+  ///
+  /// Files given explicitly must exist.
+  pub fn load(path: Option<&Path>, env_file: Option<&Path>) -> Result<Config, DuError> {
+    let source = path.unwrap_or(Path::new(DEFAULT_CONFIG_FILE)).to_path_buf();
+    let file = match path {
+      Some(path) => read_config_file(path)?,
+      None if source.exists() => read_config_file(&source)?,
+      None => ConfigFile::default(),
+    };
+    let dotenv_source = env_file.unwrap_or(Path::new(DEFAULT_DOTENV_FILE)).to_path_buf();
+    let environment = Environment::load(&dotenv_source, env_file.is_some())?;
+    let defaults = ValidationSettings::default();
+
+    Ok(Config {
+      app_id: environment.get("APP_ID").or(file.credentials.app_id),
+      app_secret: environment.get("APP_SECRET").or(file.credentials.app_secret),
+      auth_url: environment.get("AUTH_URL").or(file.credentials.auth_url),
+      base_url: environment.get("BASE_URL").or(file.project.base_url),
+      project_id: environment.get("PROJECT_ID").or(file.project.id),
+      classifier: environment.get("RUSTYDU_CLASSIFIER").or(file.classification.classifier),
+      low_confidence_classification: environment
+        .parse("RUSTYDU_LOW_CONFIDENCE_CLASSIFICATION")?
+        .or(file.classification.low_confidence)
+        .unwrap_or_default(),
+      extractors: file.extractors,
+      validation: ValidationSettings {
+        action_catalog: environment
+          .get("RUSTYDU_ACTION_CATALOG")
+          .or(file.validation.action_catalog)
+          .unwrap_or(defaults.action_catalog),
+        action_folder: environment
+          .get("RUSTYDU_ACTION_FOLDER")
+          .or(file.validation.action_folder)
+          .unwrap_or(defaults.action_folder),
+        action_priority: environment
+          .get("RUSTYDU_ACTION_PRIORITY")
+          .or(file.validation.action_priority)
+          .unwrap_or(defaults.action_priority),
+        storage_bucket: environment
+          .get("RUSTYDU_STORAGE_BUCKET")
+          .or(file.validation.storage_bucket)
+          .unwrap_or(defaults.storage_bucket),
+        storage_bucket_directory: environment
+          .get("RUSTYDU_STORAGE_BUCKET_DIRECTORY")
+          .or(file.validation.storage_bucket_directory)
+          .unwrap_or(defaults.storage_bucket_directory),
+      },
+      thresholds: Thresholds {
+        classification_confidence: environment
+          .parse("RUSTYDU_CLASSIFICATION_THRESHOLD")?
+          .or(file.thresholds.classification_confidence),
+        field_confidence: environment
+          .parse("RUSTYDU_FIELD_THRESHOLD")?
+          .or(file.thresholds.field_confidence),
+        ocr_confidence: environment
+          .parse("RUSTYDU_OCR_THRESHOLD")?
+          .or(file.thresholds.ocr_confidence),
+        document_types: file.thresholds.document_types,
+      },
+      recursive: environment
+        .parse("RUSTYDU_RECURSIVE")?
+        .or(file.input.recursive)
+        .unwrap_or(false),
+      include: file.input.include,
+      exclude: file.input.exclude,
+      extensions: environment
+        .get("RUSTYDU_EXTENSIONS")
+        .map(|extensions| {
+          extensions
+            .split(',')
+            .map(|extension| extension.trim().to_string())
+            .collect()
+        })
+        .or(file.input.extensions)
+        .unwrap_or_else(|| DEFAULT_EXTENSIONS.map(String::from).to_vec()),
+      output_directory: environment
+        .get("RUSTYDU_OUTPUT_DIR")
+        .map(PathBuf::from)
+        .or(file.output.directory)
+        .unwrap_or_else(|| PathBuf::from("output_results")),
+      prompts_directory: environment
+        .get("RUSTYDU_PROMPTS_DIR")
+        .map(PathBuf::from)
+        .or(file.output.prompts_directory)
+        .unwrap_or_else(|| PathBuf::from("generative_prompts")),
+      save_ocr: environment
+        .parse("RUSTYDU_SAVE_OCR")?
+        .or(file.output.save_ocr)
+        .unwrap_or(false),
+      multi_value_separator: environment
+        .get("RUSTYDU_MULTI_VALUE_SEPARATOR")
+        .or(file.output.multi_value_separator)
+        .unwrap_or_else(|| "; ".to_string()),
+      formats: environment
+        .get("RUSTYDU_FORMATS")
+        .map(|formats| {
+          formats
+            .split(',')
+            .map(|format| {
+              format
+                .trim()
+                .parse()
+                .map_err(|err| DuError::Config(format!("RUSTYDU_FORMATS has an invalid value: {}", err)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?
+        .or(file.output.formats)
+        .unwrap_or_else(|| vec![OutputFormat::Csv]),
+      cache_enabled: environment
+        .parse("RUSTYDU_CACHE")?
+        .or(file.cache.enabled)
+        .unwrap_or(true),
+      cache_directory: environment
+        .get("RUSTYDU_CACHE_DIR")
+        .map(PathBuf::from)
+        .or(file.cache.directory)
+        .unwrap_or_else(|| PathBuf::from(".rustydu_cache")),
+      cache_ttl: Duration::from_secs(
+        environment
+          .parse("RUSTYDU_CACHE_TTL_SECS")?
+          .or(file.cache.ttl_secs)
+          .unwrap_or(DEFAULT_CACHE_TTL_SECS),
+      ),
+      source,
+      dotenv_source,
+    })
+  }
+
+  /// Returns the credentials and endpoints, and the project ID if `needs_project` is set, or an error listing every
+  /// one of them that is not set.
+  pub fn connection(&self, needs_project: bool) -> Result<Connection, DuError> {
+    let mut required = vec![
+      (&self.app_id, "APP_ID", "credentials.app_id"),
+      (&self.app_secret, "APP_SECRET", "credentials.app_secret"),
+      (&self.auth_url, "AUTH_URL", "credentials.auth_url"),
+      (&self.base_url, "BASE_URL", "project.base_url"),
+    ];
+    if needs_project {
+      required.push((&self.project_id, "PROJECT_ID", "project.id"));
+    }
+
+    let missing: Vec<_> = required.iter().filter(|(value, ..)| value.is_none()).collect();
+    if !missing.is_empty() {
+      let env_keys: Vec<_> = missing.iter().map(|(_, env_key, _)| *env_key).collect();
+      let file_keys: Vec<_> = missing.iter().map(|(.., file_key)| format!("`{}`", file_key)).collect();
+      return Err(DuError::Config(format!(
+        "missing {}; set them as environment variables, in {}, or as {} in {}",
+        env_keys.join(", "),
+        self.dotenv_source.display(),
+        file_keys.join(", "),
+        self.source.display()
+      )));
+    }
+
+    let value = |value: &Option<String>| value.clone().unwrap_or_default();
+    Ok(Connection {
+      app_id: value(&self.app_id),
+      app_secret: value(&self.app_secret),
+      auth_url: value(&self.auth_url),
+      base_url: value(&self.base_url),
+      project_id: self.project_id.clone().filter(|_| needs_project),
+    })
+  }
+}
+
+// Reads an optional string setting, treating an empty one like the example file's `app_id = ""` as unset
+fn non_empty<'de, D: Deserializer<'de>, T: From<String>>(deserializer: D) -> Result<Option<T>, D::Error> {
+  Ok(
+    Option::<String>::deserialize(deserializer)?
+      .filter(|value| !value.is_empty())
+      .map(T::from),
+  )
+}
+
+// Reads and parses a `rustydu.toml` file
+fn read_config_file(path: &Path) -> Result<ConfigFile, DuError> {
+  let contents = fs::read_to_string(path)
+    .map_err(|err| DuError::Config(format!("cannot read configuration file {}: {}", path.display(), err)))?;
+  toml::from_str(&contents)
+    .map_err(|err| DuError::Config(format!("invalid configuration file {}: {}", path.display(), err)))
+}
+
+// The process environment backed by the variables of a dotenv file, which never overrides the process environment
+struct Environment {
+  dotenv: HashMap<String, String>,
+}
+
+impl Environment {
+  // Reads the dotenv file at `dotenv_path`, which must exist if it is `required`
+  fn load(dotenv_path: &Path, required: bool) -> Result<Environment, DuError> {
+    let mut dotenv = HashMap::new();
+    if required && !dotenv_path.exists() {
+      return Err(DuError::Config(format!(
+        "env file {} does not exist",
+        dotenv_path.display()
+      )));
+    }
+    if dotenv_path.exists() {
+      let invalid = |err: dotenvy::Error| DuError::Config(format!("invalid {}: {}", dotenv_path.display(), err));
+      for item in dotenvy::from_path_iter(dotenv_path).map_err(invalid)? {
+        let (key, value) = item.map_err(invalid)?;
+        dotenv.insert(key, value);
+      }
+    }
+    Ok(Environment { dotenv })
+  }
+
+  // Looks a variable up in the process environment, then in the dotenv file; empty values count as unset
+  fn get(&self, key: &str) -> Option<String> {
+    env::var(key)
+      .ok()
+      .filter(|value| !value.is_empty())
+      .or_else(|| self.dotenv.get(key).filter(|value| !value.is_empty()).cloned())
+  }
+
+  fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, DuError> {
+    self
+      .get(key)
+      .map(|value| {
+        value
+          .parse()
+          .map_err(|_| DuError::Config(format!("{} has an invalid value: '{}'", key, value)))
+      })
+      .transpose()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn empty_file_values_are_unset() {
+    let file: ConfigFile = toml::from_str(
+      r#"
+      [credentials]
+      app_id = ""
+      app_secret = "secret"
+
+      [output]
+      directory = ""
+      "#,
+    )
+    .unwrap();
+    assert_eq!(file.credentials.app_id, None);
+    assert_eq!(file.credentials.app_secret.as_deref(), Some("secret"));
+    assert_eq!(file.output.directory, None);
+  }
+}
@@ -1,13 +1,22 @@
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{
+  fs,
+  path::PathBuf,
+  time::{Duration, Instant},
+};
 
-use reqwest::{header::AUTHORIZATION, Client};
+use reqwest::{
+  header::{ACCEPT, CONTENT_TYPE},
+  Method, StatusCode,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-pub struct Digitize {
-  base_url: String,
-  project_id: String,
-  bearer_token: String,
-}
+use crate::{
+  cache::DigitizationCache,
+  client::{read_json, DuClient},
+  dom::DocumentObjectModel,
+  error::DuError,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Document {
@@ -15,65 +24,150 @@ struct Document {
   document_id: String,
 }
 
-impl Digitize {
-  pub fn new(base_url: &str, project_id: &str, bearer_token: &str) -> Digitize {
-    Digitize {
-      base_url: base_url.to_string(),
-      project_id: project_id.to_string(),
-      bearer_token: bearer_token.to_string(),
-    }
+// How long to wait between two polls of the digitization result endpoint
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigitizationResult {
+  /// `NotStarted`, `Running`, `Succeeded` or `Failed`.
+  pub status: String,
+  pub result: Option<DigitizationOutput>,
+  pub error: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigitizationOutput {
+  /// The DOM exactly as returned by the API; see [`DigitizationOutput::parse_document_object_model`] for the typed
+  /// view.
+  pub document_object_model: Option<Value>,
+  pub document_text: Option<String>,
+}
+
+impl DigitizationOutput {
+  /// Parses the raw DOM into typed pages, words and boxes.
+  pub fn parse_document_object_model(&self) -> Result<Option<DocumentObjectModel>, DuError> {
+    self
+      .document_object_model
+      .as_ref()
+      .map(|dom| {
+        DocumentObjectModel::deserialize(dom).map_err(|source| DuError::Deserialize {
+          source,
+          body: dom.to_string(),
+        })
+      })
+      .transpose()
   }
+}
 
-  pub async fn start(&self, document_path: &PathBuf) -> Option<String> {
+impl DuClient {
+  pub async fn digitize(&self, document_path: &PathBuf) -> Result<String, DuError> {
     // Define the API endpoint for digitization
-    let api_url = format!("{}/{}/digitization/start?api-version=1", self.base_url, self.project_id);
+    let api_url = self.project_url("digitization/start");
 
     // Read file bytes
-    let mut file = match File::open(document_path) {
-      Ok(file) => file,
-      Err(e) => {
-        println!("Error opening file: {}", e);
-        return None;
-      }
-    };
-    let mut file_content = Vec::new();
-    if let Err(e) = file.read_to_end(&mut file_content) {
-      println!("Error reading file: {}", e);
-      return None;
-    }
+    let file_content = fs::read(document_path)?;
 
     // Get MIME type
     let mime_type = mime_guess::from_path(document_path).first_or_octet_stream().to_string();
 
+    // Reuse the document if identical content was uploaded before
+    let cache = self
+      .digitization_cache
+      .as_deref()
+      .map(|cache| (cache, DigitizationCache::key(&file_content, &mime_type)));
+    if let Some((cache, key)) = &cache {
+      if let Some(document_id) = cache.document_id(key) {
+        eprintln!("[{}] Reusing cached document {}", document_path.display(), document_id);
+        return Ok(document_id);
+      }
+    }
+
     // Prepare request
-    let client = Client::new();
-    let response = client
-      .post(&api_url)
-      .header(AUTHORIZATION, format!("Bearer {}", self.bearer_token))
-      .header(reqwest::header::ACCEPT, "text/plain")
-      .body(file_content)
-      .header(reqwest::header::CONTENT_TYPE, mime_type)
-      .send()
-      .await;
+    let response = self
+      .send(Method::POST, &api_url, |request| {
+        request
+          .header(ACCEPT, "text/plain")
+          .body(file_content.clone())
+          .header(CONTENT_TYPE, &mime_type)
+      })
+      .await?;
 
     // Process response
-    match response {
-      Ok(response) => match response.status() {
-        reqwest::StatusCode::ACCEPTED => {
-          println!("Document successfully digitized!");
-          let response_data: Document = response.json().await.unwrap();
-          let document_id = response_data.document_id;
-          println!("Document ID: {}", document_id);
-          Some(document_id)
+    let response_data: Document = read_json(response).await?;
+    if let Some((cache, key)) = &cache {
+      cache.insert_document_id(key, &response_data.document_id)?;
+    }
+    Ok(response_data.document_id)
+  }
+
+  /// Polls the digitization result of `document_id` until digitization has succeeded, failed, or `timeout` has
+  /// elapsed. Results already in the digitization cache are returned without asking the API.
+  pub async fn wait_for_digitization(
+    &self,
+    document_id: &str,
+    timeout: Duration,
+  ) -> Result<DigitizationResult, DuError> {
+    let api_url = self.project_url(&format!("digitization/result/{}", document_id));
+    let deadline = Instant::now() + timeout;
+    if let Some(cache) = self.digitization_cache.as_deref() {
+      if let Some(digitization_result) = cache.digitization_result(document_id) {
+        return Ok(digitization_result);
+      }
+    }
+
+    loop {
+      let response = self.send(Method::GET, &api_url, |request| request).await?;
+      let digitization_result: DigitizationResult = match read_json(response).await {
+        Ok(digitization_result) => digitization_result,
+        Err(err) => {
+          if let DuError::Http { status, .. } = &err {
+            if *status == StatusCode::NOT_FOUND || *status == StatusCode::GONE {
+              self.forget_cached_document(document_id);
+            }
+          }
+          return Err(err);
+        }
+      };
+
+      match digitization_result.status.as_str() {
+        "Succeeded" => {
+          if let Some(cache) = self.digitization_cache.as_deref() {
+            cache.insert_digitization_result(document_id, &digitization_result)?;
+          }
+          return Ok(digitization_result);
         }
-        _ => {
-          println!("Error: {} - {}", response.status(), response.text().await.unwrap());
-          None
+        "Failed" => {
+          self.forget_cached_document(document_id);
+          return Err(DuError::UnexpectedResponse(format!(
+            "digitization of document {} failed: {}",
+            document_id,
+            digitization_result.error.unwrap_or_default()
+          )));
         }
-      },
-      Err(e) => {
-        println!("An error occurred: {}", e);
-        None
+        _ => {}
+      }
+
+      if Instant::now() + POLL_INTERVAL > deadline {
+        self.forget_cached_document(document_id);
+        return Err(DuError::Timeout(format!(
+          "digitization of document {} still {} after {:?}",
+          document_id, digitization_result.status, timeout
+        )));
+      }
+      tokio::time::sleep(POLL_INTERVAL).await;
+    }
+  }
+
+  // Drops a document whose digitization did not succeed from the digitization cache, so it is not reused
+  fn forget_cached_document(&self, document_id: &str) {
+    if let Some(cache) = self.digitization_cache.as_deref() {
+      if let Err(err) = cache.remove(document_id) {
+        eprintln!(
+          "Error removing document {} from the digitization cache: {}",
+          document_id, err
+        );
       }
     }
   }
@@ -0,0 +1,130 @@
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  client::{read_json, DuClient},
+  error::DuError,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProjectList {
+  projects: Vec<Project>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+  pub id: String,
+  pub name: String,
+  pub description: Option<String>,
+  #[serde(rename = "type")]
+  pub project_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClassifierList {
+  classifiers: Vec<Classifier>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Classifier {
+  pub id: String,
+  pub name: String,
+  pub status: Option<String>,
+  #[serde(default)]
+  pub document_type_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExtractorList {
+  extractors: Vec<Extractor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Extractor {
+  pub id: String,
+  pub name: String,
+  pub status: Option<String>,
+  pub document_type_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentTypeList {
+  document_types: Vec<DocumentType>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentType {
+  pub id: String,
+  pub name: String,
+}
+
+/// An extractor together with the schema of the fields it extracts.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractorDetails {
+  pub id: String,
+  pub name: String,
+  pub status: Option<String>,
+  pub document_type_id: Option<String>,
+  #[serde(default, alias = "columns")]
+  pub fields: Vec<FieldSchema>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldSchema {
+  pub id: String,
+  pub name: String,
+  #[serde(rename = "type")]
+  pub field_type: Option<String>,
+  #[serde(default)]
+  pub is_multi_value: bool,
+  /// Columns of a table field or parts of a composite field.
+  #[serde(default)]
+  pub components: Vec<FieldSchema>,
+}
+
+impl DuClient {
+  /// Lists the Document Understanding projects visible to the credentials.
+  pub async fn list_projects(&self) -> Result<Vec<Project>, DuError> {
+    let response = self.send(Method::GET, &self.api_url(""), |request| request).await?;
+    let project_list: ProjectList = read_json(response).await?;
+    Ok(project_list.projects)
+  }
+
+  pub async fn list_classifiers(&self) -> Result<Vec<Classifier>, DuError> {
+    let api_url = self.project_url("classifiers");
+    let response = self.send(Method::GET, &api_url, |request| request).await?;
+    let classifier_list: ClassifierList = read_json(response).await?;
+    Ok(classifier_list.classifiers)
+  }
+
+  pub async fn list_extractors(&self) -> Result<Vec<Extractor>, DuError> {
+    let api_url = self.project_url("extractors");
+    let response = self.send(Method::GET, &api_url, |request| request).await?;
+    let extractor_list: ExtractorList = read_json(response).await?;
+    Ok(extractor_list.extractors)
+  }
+
+  /// Fetches an extractor including its field schema.
+  pub async fn get_extractor(&self, extractor_id: &str) -> Result<ExtractorDetails, DuError> {
+    let api_url = self.project_url(&format!("extractors/{}", extractor_id));
+    let response = self.send(Method::GET, &api_url, |request| request).await?;
+    read_json(response).await
+  }
+
+  pub async fn list_document_types(&self) -> Result<Vec<DocumentType>, DuError> {
+    let api_url = self.project_url("document-types");
+    let response = self.send(Method::GET, &api_url, |request| request).await?;
+    let document_type_list: DocumentTypeList = read_json(response).await?;
+    Ok(document_type_list.document_types)
+  }
+}
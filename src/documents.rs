@@ -0,0 +1,141 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::error::DuError;
+
+/// File extensions processed when no allow-list is configured.
+pub const DEFAULT_EXTENSIONS: [&str; 8] = ["png", "jpe", "jpg", "jpeg", "tiff", "tif", "bmp", "pdf"];
+
+/// Decides which files of an input folder are documents to process.
+///
+/// Glob patterns are matched against the path relative to the input folder, e.g. `2024-*/acme/*.pdf`.
+pub struct DocumentFilter {
+  include: Option<GlobSet>,
+  exclude: GlobSet,
+  extensions: Vec<String>,
+}
+
+impl DocumentFilter {
+  /// Accepts files with one of `extensions` (case-insensitive) that match any `include` pattern, or every file when
+  /// there are none, and no `exclude` pattern.
+  pub fn new(include: &[String], exclude: &[String], extensions: &[String]) -> Result<DocumentFilter, DuError> {
+    Ok(DocumentFilter {
+      include: if include.is_empty() {
+        None
+      } else {
+        Some(glob_set(include)?)
+      },
+      exclude: glob_set(exclude)?,
+      extensions: extensions
+        .iter()
+        .map(|extension| extension.trim_start_matches('.').to_lowercase())
+        .collect(),
+    })
+  }
+
+  /// Whether the file at `relative_path` inside the input folder is a document to process.
+  pub fn accepts(&self, relative_path: &Path) -> bool {
+    let has_extension = relative_path
+      .extension()
+      .map(|extension| extension.to_string_lossy().to_lowercase())
+      .is_some_and(|extension| self.extensions.contains(&extension));
+    let included = match &self.include {
+      Some(include) => include.is_match(relative_path),
+      None => true,
+    };
+    has_extension && included && !self.exclude.is_match(relative_path)
+  }
+}
+
+// Compiles glob patterns into one matcher
+fn glob_set(patterns: &[String]) -> Result<GlobSet, DuError> {
+  let mut builder = GlobSetBuilder::new();
+  for pattern in patterns {
+    let glob = Glob::new(pattern).map_err(|err| DuError::Config(format!("invalid glob '{}': {}", pattern, err)))?;
+    builder.add(glob);
+  }
+  builder
+    .build()
+    .map_err(|err| DuError::Config(format!("invalid globs: {}", err)))
+}
+
+/// Lists the documents in `folder` accepted by `filter`, descending into subfolders if `recursive` is set. The result
+/// is sorted so runs process documents in a stable order.
+pub fn collect_documents(folder: &Path, recursive: bool, filter: &DocumentFilter) -> Result<Vec<PathBuf>, DuError> {
+  let mut documents = Vec::new();
+  let mut folders = vec![folder.to_path_buf()];
+  while let Some(current) = folders.pop() {
+    for entry in fs::read_dir(&current)? {
+      let entry = entry?;
+      let path = entry.path();
+      let file_type = entry.file_type()?;
+      if file_type.is_dir() {
+        if recursive {
+          folders.push(path);
+        }
+      } else if filter.accepts(path.strip_prefix(folder).unwrap_or(&path)) {
+        documents.push(path);
+      }
+    }
+  }
+  documents.sort();
+  Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+  }
+
+  fn filter(include: &[&str], exclude: &[&str]) -> DocumentFilter {
+    DocumentFilter::new(&strings(include), &strings(exclude), &strings(&DEFAULT_EXTENSIONS)).unwrap()
+  }
+
+  #[test]
+  fn accepts_allowed_extensions_in_any_case() {
+    let filter = filter(&[], &[]);
+    assert!(filter.accepts(Path::new("invoice.pdf")));
+    assert!(filter.accepts(Path::new("scans/receipt.JPG")));
+    assert!(!filter.accepts(Path::new("notes.txt")));
+    assert!(!filter.accepts(Path::new("README")));
+  }
+
+  #[test]
+  fn extensions_may_be_given_with_a_dot() {
+    let filter = DocumentFilter::new(&[], &[], &strings(&[".PDF"])).unwrap();
+    assert!(filter.accepts(Path::new("invoice.pdf")));
+    assert!(!filter.accepts(Path::new("invoice.png")));
+  }
+
+  #[test]
+  fn include_patterns_select_by_relative_path() {
+    let filter = filter(&["2024-*/**"], &[]);
+    assert!(filter.accepts(Path::new("2024-01/acme/invoice.pdf")));
+    assert!(!filter.accepts(Path::new("2023-12/invoice.pdf")));
+  }
+
+  #[test]
+  fn exclude_patterns_win_over_include_patterns() {
+    let filter = filter(&["**/*.pdf"], &["**/drafts/**"]);
+    assert!(filter.accepts(Path::new("2024/invoice.pdf")));
+    assert!(!filter.accepts(Path::new("2024/drafts/invoice.pdf")));
+  }
+
+  #[test]
+  fn include_patterns_do_not_bypass_the_extension_allow_list() {
+    assert!(!filter(&["**"], &[]).accepts(Path::new("notes.txt")));
+  }
+
+  #[test]
+  fn invalid_globs_are_configuration_errors() {
+    let result = DocumentFilter::new(&strings(&["[a-"]), &[], &[]);
+    assert!(matches!(result, Err(DuError::Config(_))));
+  }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+/// Document Object Model produced by digitization: the OCR layer of a document, page by page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DocumentObjectModel {
+  pub document_id: String,
+  pub content_type: Option<String>,
+  pub length: Option<usize>,
+  #[serde(default)]
+  pub pages: Vec<Page>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Page {
+  pub page_index: usize,
+  /// Page width and height.
+  #[serde(default)]
+  pub size: Vec<f64>,
+  pub skew_angle: Option<f64>,
+  /// Offset of the page's first character in the document text.
+  pub index_in_text: Option<usize>,
+  pub page_length: Option<usize>,
+  #[serde(default)]
+  pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Section {
+  pub language: Option<String>,
+  pub rotation: Option<String>,
+  #[serde(rename = "Type")]
+  pub section_type: Option<String>,
+  #[serde(default)]
+  pub word_groups: Vec<WordGroup>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct WordGroup {
+  #[serde(rename = "Type")]
+  pub word_group_type: Option<String>,
+  #[serde(default)]
+  pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Word {
+  pub text: String,
+  #[serde(rename = "Box")]
+  pub bounding_box: BoundingBox,
+  /// Offset of the word in the document text.
+  pub index_in_text: Option<usize>,
+  pub ocr_confidence: Option<f64>,
+  pub visual_line_number: Option<usize>,
+}
+
+/// Position of a word on its page, sent by the API as `[top, left, width, height]`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f64; 4]", into = "[f64; 4]")]
+pub struct BoundingBox {
+  pub top: f64,
+  pub left: f64,
+  pub width: f64,
+  pub height: f64,
+}
+
+impl From<[f64; 4]> for BoundingBox {
+  fn from([top, left, width, height]: [f64; 4]) -> Self {
+    BoundingBox {
+      top,
+      left,
+      width,
+      height,
+    }
+  }
+}
+
+impl From<BoundingBox> for [f64; 4] {
+  fn from(bounding_box: BoundingBox) -> Self {
+    [
+      bounding_box.top,
+      bounding_box.left,
+      bounding_box.width,
+      bounding_box.height,
+    ]
+  }
+}
+
+impl DocumentObjectModel {
+  /// Iterates over every word of the document in page order.
+  pub fn words(&self) -> impl Iterator<Item = &Word> {
+    self.pages.iter().flat_map(Page::words)
+  }
+}
+
+impl Page {
+  pub fn words(&self) -> impl Iterator<Item = &Word> {
+    self
+      .sections
+      .iter()
+      .flat_map(|section| &section.word_groups)
+      .flat_map(|word_group| &word_group.words)
+  }
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Errors returned by the Document Understanding API clients and result writers.
+#[derive(Debug, Error)]
+pub enum DuError {
+  /// The API answered with a non-success status code.
+  #[error("HTTP {status}: {body}")]
+  Http { status: StatusCode, body: String },
+
+  /// The request never produced a response (connection, DNS, TLS, ...).
+  #[error("transport error: {0}")]
+  Transport(reqwest::Error),
+
+  /// The response body did not match the expected schema.
+  #[error("failed to deserialize response: {source}")]
+  Deserialize {
+    #[source]
+    source: serde_json::Error,
+    body: String,
+  },
+
+  /// The response was well-formed but not what the workflow expected, e.g. a failed operation.
+  #[error("unexpected response: {0}")]
+  UnexpectedResponse(String),
+
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+
+  #[error("CSV error: {0}")]
+  Csv(#[from] csv::Error),
+
+  #[error("Excel error: {0}")]
+  Xlsx(#[from] rust_xlsxwriter::XlsxError),
+
+  /// The identity server refused to issue a token.
+  #[error("authentication failed: {0}")]
+  Auth(String),
+
+  #[error("timed out: {0}")]
+  Timeout(String),
+
+  /// Missing or invalid configuration such as an unset environment variable.
+  #[error("configuration error: {0}")]
+  Config(String),
+}
+
+impl DuError {
+  /// Whether the error means the credentials were refused, either by the identity server or, with a 401 or 403, by the
+  /// API itself, e.g. because the client was revoked or lacks a scope.
+  pub fn is_auth_failure(&self) -> bool {
+    match self {
+      DuError::Auth(_) => true,
+      DuError::Http { status, .. } => *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN,
+      _ => false,
+    }
+  }
+}
+
+impl From<reqwest::Error> for DuError {
+  fn from(err: reqwest::Error) -> Self {
+    if err.is_timeout() {
+      DuError::Timeout(err.to_string())
+    } else {
+      DuError::Transport(err)
+    }
+  }
+}
@@ -1,20 +1,19 @@
-use reqwest::{
-  header::{AUTHORIZATION, CONTENT_TYPE},
-  Client,
-};
+use reqwest::{header::CONTENT_TYPE, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-pub struct Extract {
-  base_url: String,
-  project_id: String,
-  bearer_token: String,
-}
+use crate::{
+  client::{read_json, DuClient},
+  error::DuError,
+};
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct ExtractionData<'a> {
   document_id: &'a str,
+  /// Pages to extract from, e.g. `1-2`; the whole document when absent.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  page_range: Option<&'a str>,
   #[serde(flatten)]
   prompts: Option<Value>,
 }
@@ -147,57 +146,34 @@ pub struct ColumnInfo {
   pub field_type: String,
 }
 
-impl Extract {
-  pub fn new(base_url: &str, project_id: &str, bearer_token: &str) -> Extract {
-    Extract {
-      base_url: base_url.to_string(),
-      project_id: project_id.to_string(),
-      bearer_token: bearer_token.to_string(),
-    }
-  }
-
-  pub async fn extract_document(
+impl DuClient {
+  /// Extracts `document_id` with `extractor_id`, limited to `page_range` (counted from 1, e.g. `3-4`) when given.
+  pub async fn extract(
     &self,
     extractor_id: &str,
     document_id: &str,
+    page_range: Option<&str>,
     prompts: Option<Value>,
-  ) -> Option<ExtractionResults> {
+  ) -> Result<ExtractionResults, DuError> {
     // Define the API endpoint for document extraction
-    let api_url = format!(
-      "{}/{}/extractors/{}/extraction?api-version=1",
-      self.base_url, self.project_id, extractor_id
-    );
+    let api_url = self.project_url(&format!("extractors/{}/extraction", extractor_id));
 
     // Prepare request data
-    let data = ExtractionData { document_id, prompts };
+    let data = ExtractionData {
+      document_id,
+      page_range,
+      prompts,
+    };
 
     // Prepare request
-    let client = Client::new();
-    let response = client
-      .post(&api_url)
-      .header(AUTHORIZATION, format!("Bearer {}", self.bearer_token))
-      .header(CONTENT_TYPE, "application/json")
-      .json(&data)
-      .send()
-      .await;
+    let response = self
+      .send(Method::POST, &api_url, |request| {
+        request.header(CONTENT_TYPE, "application/json").json(&data)
+      })
+      .await?;
 
     // Process response
-    match response {
-      Ok(response) => match response.status() {
-        reqwest::StatusCode::OK => {
-          println!("Document successfully extracted!\n");
-          let extraction_results: ExtractionResults = response.json().await.unwrap();
-          Some(extraction_results)
-        }
-        _ => {
-          println!("Error: {} - {}", response.status(), response.text().await.unwrap());
-          None
-        }
-      },
-      Err(e) => {
-        println!("An error occurred during extraction: {}", e);
-        None
-      }
-    }
+    let extraction_results: ExtractionResults = read_json(response).await?;
+    Ok(extraction_results)
   }
 }
@@ -1,257 +1,523 @@
 pub mod auth;
+pub mod cache;
 pub mod classify;
+pub mod cli;
+pub mod client;
+pub mod config;
 pub mod digitize;
+pub mod discovery;
+pub mod documents;
+pub mod dom;
+pub mod error;
 pub mod extract;
+pub mod manifest;
+pub mod pipeline;
+pub mod report;
 pub mod result_utils;
+pub mod retry;
+pub mod thresholds;
 pub mod validate;
+pub mod watch;
 
-use std::{env, path::PathBuf};
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
 
 use auth::Authentication;
-use clap::{Arg, Command};
-use classify::Classify;
-use digitize::Digitize;
-use extract::Extract;
-use result_utils::CSVWriter;
-use serde_json::Value;
-use validate::Validate;
-
-// Load environment variables
-fn load_env_vars() -> (String, String, String, String, String) {
-  (
-    env::var("APP_ID").unwrap(),
-    env::var("APP_SECRET").unwrap(),
-    env::var("AUTH_URL").unwrap(),
-    env::var("BASE_URL").unwrap(),
-    env::var("PROJECT_ID").unwrap(),
-  )
+use cache::DigitizationCache;
+use clap::ArgMatches;
+use classify::ClassificationResults;
+use client::DuClient;
+use config::Config;
+use discovery::FieldSchema;
+use documents::DocumentFilter;
+use error::DuError;
+use extract::ExtractionResults;
+use pipeline::{Pipeline, ProcessingOptions};
+use reqwest::StatusCode;
+use result_utils::OutputFormat;
+use retry::RetryPolicy;
+use serde::{de::DeserializeOwned, Serialize};
+use thresholds::{LowConfidenceClassification, Thresholds};
+use watch::WatchOptions;
+
+// Process exit codes, listed under "Exit Codes" in the README so scripts can rely on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+  Success = 0,
+  PartialFailure = 1,
+  AllFailed = 2,
+  ConfigError = 3,
+  AuthFailure = 4,
 }
 
-// Function to load prompts from a JSON file based on the document type ID
-fn load_prompts(document_type_id: &str) -> Option<Value> {
-  let prompts_directory = "Generative Prompts";
-  let prompts_file = format!("{}/{}_prompts.json", prompts_directory, document_type_id);
-  match std::fs::read_to_string(prompts_file) {
-    Ok(contents) => match serde_json::from_str(&contents) {
-      Ok(prompts) => Some(prompts),
-      Err(err) => {
-        eprintln!("Error parsing JSON prompts: {}", err);
-        None
-      }
-    },
-    Err(err) => {
-      eprintln!("Error reading prompts file: {}", err);
-      None
+impl ExitStatus {
+  // Status of a batch of `document_count` documents; documents without an outcome, because their task panicked or
+  // --fail-fast stopped the batch first, count as failed
+  fn of_batch(document_count: usize, outcomes: &[(PathBuf, Result<(), DuError>)]) -> ExitStatus {
+    let succeeded = outcomes.iter().filter(|(_, result)| result.is_ok()).count();
+    let auth_failures = outcomes
+      .iter()
+      .filter(|(_, result)| result.as_ref().is_err_and(DuError::is_auth_failure))
+      .count();
+    if succeeded == document_count {
+      ExitStatus::Success
+    } else if succeeded > 0 {
+      ExitStatus::PartialFailure
+    } else if auth_failures > 0 && auth_failures == outcomes.len() {
+      // The token could not be renewed mid-run or the API refused it, so no document stood a chance
+      ExitStatus::AuthFailure
+    } else {
+      ExitStatus::AllFailed
     }
   }
+
+  // Status of a command that stopped with `err`
+  fn of_error(err: &DuError) -> ExitStatus {
+    match err {
+      DuError::Config(_) => ExitStatus::ConfigError,
+      err if err.is_auth_failure() => ExitStatus::AuthFailure,
+      _ => ExitStatus::AllFailed,
+    }
+  }
+}
+
+// Authenticate and build the API client, for the configured project if `needs_project` is set
+async fn connect(config: &Config, needs_project: bool, retry: RetryPolicy) -> Result<DuClient, DuError> {
+  let connection = config.connection(needs_project)?;
+
+  // Every request, including the token fetch, goes through one HTTP client so connections are pooled
+  let http = reqwest::Client::new();
+
+  // Initialize Authentication and fetch the first token up front so bad credentials fail before any document
+  let auth = Arc::new(
+    Authentication::new(
+      http.clone(),
+      &connection.app_id,
+      &connection.app_secret,
+      &connection.auth_url,
+    )
+    .with_retry_policy(retry.clone()),
+  );
+  auth.get_bearer_token().await?;
+
+  // Initialize the API client
+  let du_client = DuClient::new(http, &connection.base_url, auth)
+    .with_retry_policy(retry)
+    .with_validation_settings(config.validation.clone());
+  let Some(project_id) = connection.project_id else {
+    return Ok(du_client);
+  };
+  let du_client = du_client.with_project(&project_id);
+  if config.cache_enabled {
+    let cache = DigitizationCache::new(config.cache_directory.join(&project_id), config.cache_ttl);
+    return Ok(du_client.with_digitization_cache(cache));
+  }
+  Ok(du_client)
 }
 
 // Main function to process documents in the folder
 async fn process_documents_in_folder(
-  folder_path: &PathBuf,
-  validate_classification: bool,
-  validate_extraction: bool,
-  generative_classification: bool,
-  generative_extraction: bool,
-  output_directory: PathBuf,
-) {
-  // Load environment variables
-  let (app_id, app_secret, auth_url, base_url, project_id) = load_env_vars();
-
-  // Initialize Authentication
-  let auth = Authentication::new(&app_id, &app_secret, &auth_url);
-  let bearer_token = auth.get_bearer_token().await.unwrap();
-
-  // Initialize API clients
-  let digitize_client = Digitize::new(&base_url, &project_id, &bearer_token);
-  let classify_client = Classify::new(&base_url, &project_id, &bearer_token);
-  let extract_client = Extract::new(&base_url, &project_id, &bearer_token);
-  let validate_client = Validate::new(&base_url, &project_id, &bearer_token);
-
-  // Load classification prompts if generative_classification is enabled
-  let classifier = if generative_classification {
-    "generative_classifier"
-  } else {
-    "ml-classification"
+  folder_path: &Path,
+  recursive: bool,
+  filter: &DocumentFilter,
+  options: ProcessingOptions,
+  config: &Config,
+  retry: RetryPolicy,
+) -> Result<ExitStatus, DuError> {
+  // Collect the documents first so a bad folder fails before authenticating
+  if !folder_path.is_dir() {
+    return Err(DuError::Config(format!("{} is not a folder", folder_path.display())));
+  }
+  let documents = documents::collect_documents(folder_path, recursive, filter)?;
+  let document_count = documents.len();
+
+  let du_client = connect(config, true, retry).await?;
+  let pipeline = Pipeline::new(du_client, options)?;
+  let outcomes = pipeline.run(documents).await;
+  Ok(ExitStatus::of_batch(document_count, &outcomes))
+}
+
+// Prints the projects, or the classifiers, document types and extractors of one project
+async fn discover(project_id: Option<&String>, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let du_client = connect(config, false, retry).await?;
+  let Some(project_id) = project_id else {
+    println!("Projects:");
+    for project in du_client.list_projects().await? {
+      println!(
+        "  {}  {} ({})",
+        project.id,
+        project.name,
+        project.project_type.as_deref().unwrap_or("unknown type")
+      );
+    }
+    return Ok(());
+  };
+
+  let du_client = du_client.with_project(project_id);
+
+  println!("Classifiers:");
+  for classifier in du_client.list_classifiers().await? {
+    println!(
+      "  {}  {} [{}]  document types: {}",
+      classifier.id,
+      classifier.name,
+      classifier.status.as_deref().unwrap_or("unknown"),
+      classifier.document_type_ids.join(", ")
+    );
+  }
+
+  println!("Document types:");
+  for document_type in du_client.list_document_types().await? {
+    println!("  {}  {}", document_type.id, document_type.name);
+  }
+
+  println!("Extractors:");
+  for extractor in du_client.list_extractors().await? {
+    let details = du_client.get_extractor(&extractor.id).await?;
+    println!(
+      "  {}  {} [{}]  document type: {}",
+      details.id,
+      details.name,
+      details.status.as_deref().unwrap_or("unknown"),
+      details.document_type_id.as_deref().unwrap_or("none")
+    );
+    print_field_schema(&details.fields, 2);
+  }
+  Ok(())
+}
+
+fn print_field_schema(fields: &[FieldSchema], depth: usize) {
+  for field in fields {
+    println!(
+      "{}- {} ({}): {}{}",
+      "  ".repeat(depth),
+      field.name,
+      field.id,
+      field.field_type.as_deref().unwrap_or("unknown"),
+      if field.is_multi_value { ", multi-value" } else { "" }
+    );
+    print_field_schema(&field.components, depth + 1);
+  }
+}
+
+// Builds the retry policy from the defaults overridden by any retry options given on the command line
+fn retry_policy_from_matches(matches: &ArgMatches) -> RetryPolicy {
+  let mut retry = RetryPolicy::default();
+  if let Some(&max_attempts) = matches.get_one::<u32>("max_attempts") {
+    retry.max_attempts = max_attempts;
+  }
+  if let Some(&base_delay) = matches.get_one::<u64>("retry_base_delay_ms") {
+    retry.base_delay = Duration::from_millis(base_delay);
+  }
+  if let Some(&max_delay) = matches.get_one::<u64>("retry_max_delay_ms") {
+    retry.max_delay = Duration::from_millis(max_delay);
+  }
+  if let Some(&jitter) = matches.get_one::<u64>("retry_jitter_ms") {
+    retry.jitter = Duration::from_millis(jitter);
+  }
+  if let Some(statuses) = matches.get_many::<u16>("retry_status") {
+    retry.retry_statuses = statuses.filter_map(|&code| StatusCode::from_u16(code).ok()).collect();
+  }
+  retry
+}
+
+// Reads a JSON file such as stored classification or extraction results
+fn read_json_file<T: DeserializeOwned>(path: &PathBuf) -> Result<T, DuError> {
+  let contents = fs::read_to_string(path)?;
+  serde_json::from_str(&contents).map_err(|source| DuError::Deserialize { source, body: contents })
+}
+
+// Writes `value` as pretty JSON to `output`, or to stdout when no output file is given
+fn write_json<T: Serialize>(value: &T, output: Option<&PathBuf>) -> Result<(), DuError> {
+  let json = serde_json::to_string_pretty(value).map_err(io::Error::from)?;
+  match output {
+    Some(output) => fs::write(output, json)?,
+    None => println!("{}", json),
+  }
+  Ok(())
+}
+
+fn digitization_timeout(matches: &ArgMatches) -> Duration {
+  Duration::from_secs(
+    *matches
+      .get_one::<u64>("digitization_timeout_secs")
+      .expect("has default"),
+  )
+}
+
+// The classifier given on the command line, else the configured one, else the ML classifier
+fn classifier<'a>(matches: &'a ArgMatches, config: &'a Config) -> &'a str {
+  matches
+    .get_one::<String>("classifier")
+    .or(config.classifier.as_ref())
+    .map_or("ml-classification", String::as_str)
+}
+
+// Builds the pipeline settings from the options shared by `pipeline` and `watch`, falling back to the configuration
+fn processing_options(matches: &ArgMatches, config: &Config, folder_path: &Path) -> ProcessingOptions {
+  let generative_classification = matches.get_flag("generative_classification");
+  // --generative-classification overrides a configured classifier, but not one given with --classifier
+  let classifier = match matches.get_one::<String>("classifier") {
+    Some(classifier) => classifier.clone(),
+    None if generative_classification => "generative_classifier".to_string(),
+    None => config
+      .classifier
+      .clone()
+      .unwrap_or_else(|| "ml-classification".to_string()),
+  };
+  // Mappings given on the command line replace configured ones for the same document type
+  let mut extractors = config.extractors.clone();
+  if let Some(mappings) = matches.get_many::<(String, String)>("extractor") {
+    extractors.extend(mappings.cloned());
+  }
+  ProcessingOptions {
+    validate_classification: matches.get_flag("validate_classification"),
+    validate_extraction: matches.get_flag("validate_extraction"),
+    generative_classification,
+    generative_extraction: matches.get_flag("generative_extraction"),
+    classifier,
+    extractors,
+    input_directory: folder_path.to_path_buf(),
+    output_directory: matches
+      .get_one::<PathBuf>("output_dir")
+      .unwrap_or(&config.output_directory)
+      .clone(),
+    prompts_directory: matches
+      .get_one::<PathBuf>("prompts_dir")
+      .unwrap_or(&config.prompts_directory)
+      .clone(),
+    save_ocr: matches.get_flag("save_ocr") || config.save_ocr,
+    multi_value_separator: matches
+      .get_one::<String>("multi_value_separator")
+      .unwrap_or(&config.multi_value_separator)
+      .clone(),
+    formats: output_formats(matches, config),
+    // Only `pipeline` has the flag; a watcher keeps going after a failed document
+    fail_fast: matches
+      .try_get_one::<bool>("fail_fast")
+      .ok()
+      .flatten()
+      .copied()
+      .unwrap_or(false),
+    thresholds: thresholds(matches, config),
+    low_confidence_classification: matches
+      .get_one::<LowConfidenceClassification>("low_confidence_classification")
+      .copied()
+      .unwrap_or(config.low_confidence_classification),
+    concurrency: *matches.get_one::<usize>("concurrency").expect("has default"),
+    digitization_timeout: digitization_timeout(matches),
+  }
+}
+
+// The configured thresholds with the ones given on the command line on top
+fn thresholds(matches: &ArgMatches, config: &Config) -> Thresholds {
+  let mut thresholds = config.thresholds.clone();
+  if let Some(confidence) = matches.get_one::<f32>("classification_threshold") {
+    thresholds.classification_confidence = Some(*confidence);
+  }
+  if let Some(confidence) = matches.get_one::<f32>("field_threshold") {
+    thresholds.field_confidence = Some(*confidence);
+  }
+  if let Some(confidence) = matches.get_one::<f32>("ocr_threshold") {
+    thresholds.ocr_confidence = Some(*confidence);
+  }
+  for (document_type_id, field, confidence) in matches
+    .get_many::<(String, Option<String>, f32)>("threshold")
+    .into_iter()
+    .flatten()
+  {
+    let document_type = thresholds.document_types.entry(document_type_id.clone()).or_default();
+    match field {
+      Some(field) => {
+        document_type.fields.insert(field.clone(), *confidence);
+      }
+      None => document_type.field_confidence = Some(*confidence),
+    }
+  }
+  thresholds
+}
+
+// The formats given with --format, which replace the configured ones; a format named twice is written once
+fn output_formats(matches: &ArgMatches, config: &Config) -> Vec<OutputFormat> {
+  let selected: Vec<OutputFormat> = match matches.get_many::<OutputFormat>("format") {
+    Some(formats) => formats.copied().collect(),
+    None => config.formats.clone(),
   };
-  let classification_prompts = if generative_classification {
-    load_prompts("classification")
-  } else {
-    None
+  let mut formats = Vec::new();
+  for format in selected {
+    if !formats.contains(&format) {
+      formats.push(format);
+    }
+  }
+  formats
+}
+
+// Builds the document filter from the input options, whose patterns and extensions replace the configured ones, plus
+// `excluded` patterns that always apply
+fn document_filter(matches: &ArgMatches, config: &Config, excluded: &[&str]) -> Result<DocumentFilter, DuError> {
+  let list = |id: &str, configured: &Vec<String>| {
+    matches
+      .get_many::<String>(id)
+      .map_or_else(|| configured.clone(), |values| values.cloned().collect::<Vec<_>>())
   };
+  let mut exclude = list("exclude", &config.exclude);
+  exclude.extend(excluded.iter().map(|pattern| pattern.to_string()));
+  DocumentFilter::new(
+    &list("include", &config.include),
+    &exclude,
+    &list("extension", &config.extensions),
+  )
+}
+
+async fn run_pipeline(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<ExitStatus, DuError> {
+  let folder_path = matches.get_one::<PathBuf>("folder").expect("required");
+  let options = processing_options(matches, config, folder_path);
+  let filter = document_filter(matches, config, &[])?;
+  let recursive = matches.get_flag("recursive") || config.recursive;
+
+  process_documents_in_folder(folder_path, recursive, &filter, options, config, retry).await
+}
+
+async fn run_watch(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let folder_path = matches.get_one::<PathBuf>("folder").expect("required");
+  let options = processing_options(matches, config, folder_path);
+  // Documents that were moved away after processing must not be picked up again
+  let processed = format!("{}/**", watch::PROCESSED_DIRECTORY);
+  let failed = format!("{}/**", watch::FAILED_DIRECTORY);
+  let filter = document_filter(matches, config, &[&processed, &failed])?;
+  let watch_options = WatchOptions {
+    recursive: matches.get_flag("recursive") || config.recursive,
+    poll_interval: Duration::from_secs(*matches.get_one::<u64>("poll_interval_secs").expect("has default")),
+    settle_time: Duration::from_secs(*matches.get_one::<u64>("settle_secs").expect("has default")),
+  };
+  if !folder_path.is_dir() {
+    return Err(DuError::Config(format!("{} is not a folder", folder_path.display())));
+  }
+
+  let du_client = connect(config, true, retry).await?;
+  let pipeline = Pipeline::new(du_client, options)?;
+  watch::watch(&pipeline, folder_path, &filter, &watch_options).await
+}
+
+async fn run_digitize(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let du_client = connect(config, true, retry).await?;
+  let file = matches.get_one::<PathBuf>("file").expect("required");
+
+  let document_id = du_client.digitize(file).await?;
+  let digitization_result = du_client
+    .wait_for_digitization(&document_id, digitization_timeout(matches))
+    .await?;
+  if let Some(output) = matches.get_one::<PathBuf>("output") {
+    write_json(&digitization_result, Some(output))?;
+  }
+  println!("{}", document_id);
+  Ok(())
+}
+
+async fn run_classify(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let du_client = connect(config, true, retry).await?;
+  let document_id = matches.get_one::<String>("document_id").expect("required");
+  let classifier = classifier(matches, config);
+  let prompts = matches.get_one::<PathBuf>("prompts").map(read_json_file).transpose()?;
 
-  // Iterate through files in the specified folder
-  for entry in std::fs::read_dir(folder_path).unwrap() {
-    if let Ok(entry) = entry {
-      let path = entry.path();
-      if let Some(extension) = path.extension() {
-        let extension = extension.to_string_lossy().to_lowercase();
-        if extension == "png"
-          || extension == "jpe"
-          || extension == "jpg"
-          || extension == "jpeg"
-          || extension == "tiff"
-          || extension == "tif"
-          || extension == "bmp"
-          || extension == "pdf"
-        {
-          println!("Processing document: {:?}", path);
-          match digitize_client.start(&path).await {
-            Some(document_id) => {
-              match classify_client
-                .classify_document(&document_id, classifier, classification_prompts.clone())
-                .await
-              {
-                Some(classification_results) => {
-                  if validate_classification {
-                    if let Some(document_type_id) = validate_client
-                      .validate_classification_results(&document_id, &classification_results)
-                      .await
-                    {
-                      let extraction_prompts = if generative_extraction {
-                        load_prompts(&document_type_id)
-                      } else {
-                        None
-                      };
-                      let classification_results = if generative_extraction {
-                        "generative_extractor"
-                      } else {
-                        &classification_results
-                      };
-                      if let Some(extraction_results) = extract_client
-                        .extract_document(classification_results, &document_id, extraction_prompts)
-                        .await
-                      {
-                        if !validate_extraction {
-                          if let Err(err) =
-                            CSVWriter::write_extraction_results_to_csv(&extraction_results, &path, &output_directory)
-                          {
-                            eprintln!("Error writing extraction results to CSV: {}", err);
-                          }
-                          CSVWriter::print_csv_results(&path, &output_directory);
-                        } else {
-                          if let Some(validated_results) = validate_client
-                            .validate_extraction_results(&document_type_id, &document_id, &extraction_results)
-                            .await
-                          {
-                            if let Err(err) = CSVWriter::write_validated_results_to_csv(
-                              &validated_results,
-                              &extraction_results,
-                              &path,
-                              &output_directory,
-                            ) {
-                              eprintln!("Error writing validated results to CSV: {}", err);
-                            }
-                            CSVWriter::print_csv_results(&path, &output_directory);
-                          }
-                        }
-                      }
-                    }
-                  } else {
-                    for result in &classification_results.classification_results {
-                      let document_type_id = &result.document_type_id;
-                      let extraction_prompts = if generative_extraction {
-                        load_prompts(document_type_id)
-                      } else {
-                        None
-                      };
-                      let document_type_id = if generative_extraction {
-                        "generative_extractor"
-                      } else {
-                        document_type_id
-                      };
-                      if let Some(extraction_results) = extract_client
-                        .extract_document(document_type_id, &document_id, extraction_prompts)
-                        .await
-                      {
-                        if !validate_extraction {
-                          if let Err(err) =
-                            CSVWriter::write_extraction_results_to_csv(&extraction_results, &path, &output_directory)
-                          {
-                            eprintln!("Error writing extraction results to CSV: {}", err);
-                          }
-                        } else {
-                          if let Some(validated_results) = validate_client
-                            .validate_extraction_results(&document_type_id, &document_id, &extraction_results)
-                            .await
-                          {
-                            if let Err(err) = CSVWriter::write_validated_results_to_csv(
-                              &validated_results,
-                              &extraction_results,
-                              &path,
-                              &output_directory,
-                            ) {
-                              eprintln!("Error writing validated results to CSV: {}", err);
-                            }
-                          }
-                        }
-                      }
-                    }
-                  }
-                }
-                None => println!("Error classifying document {:?}", path),
-              }
-            }
-            None => println!("Error digitizing document {:?}", path),
-          }
+  let classification_results = du_client.classify(document_id, classifier, prompts).await?;
+  write_json(&classification_results, matches.get_one::<PathBuf>("output"))
+}
+
+async fn run_extract(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let du_client = connect(config, true, retry).await?;
+  let document_id = matches.get_one::<String>("document_id").expect("required");
+  let extractor_id = matches.get_one::<String>("extractor").expect("required");
+  let prompts = matches.get_one::<PathBuf>("prompts").map(read_json_file).transpose()?;
+
+  let extraction_results = du_client.extract(extractor_id, document_id, None, prompts).await?;
+  write_json(&extraction_results, matches.get_one::<PathBuf>("output"))
+}
+
+async fn run_validate(matches: &ArgMatches, config: &Config, retry: RetryPolicy) -> Result<(), DuError> {
+  let du_client = connect(config, true, retry).await?;
+  match matches.subcommand() {
+    Some(("classification", matches)) => {
+      let document_id = matches.get_one::<String>("document_id").expect("required");
+      let classifier = classifier(matches, config);
+      let classification_results: ClassificationResults =
+        read_json_file(matches.get_one::<PathBuf>("input").expect("required"))?;
+
+      let validated = du_client
+        .validate_classification(classifier, document_id, &classification_results)
+        .await?;
+      // A file holding several documents gets a line per document with its pages
+      let split = validated.len() > 1;
+      for validated in &validated {
+        match validated.pages.filter(|_| split) {
+          Some((first, last)) => println!("{} {}-{}", validated.document_type_id, first, last),
+          None => println!("{}", validated.document_type_id),
         }
       }
+      Ok(())
+    }
+    Some(("extraction", matches)) => {
+      let extractor_id = matches.get_one::<String>("extractor").expect("required");
+      let extraction_results: ExtractionResults =
+        read_json_file(matches.get_one::<PathBuf>("input").expect("required"))?;
+
+      let validated_results = du_client
+        .validate_extraction(extractor_id, &extraction_results.document_id, &extraction_results)
+        .await?;
+      write_json(&validated_results, matches.get_one::<PathBuf>("output"))
     }
+    _ => unreachable!("subcommand is required"),
+  }
+}
+
+// Resolves the configuration and runs the selected subcommand
+async fn run(matches: &ArgMatches) -> Result<ExitStatus, DuError> {
+  let retry = retry_policy_from_matches(matches);
+  let mut config = Config::load(
+    matches.get_one::<PathBuf>("config").map(PathBuf::as_path),
+    matches.get_one::<PathBuf>("env_file").map(PathBuf::as_path),
+  )?;
+  if let Some(project_id) = matches.get_one::<String>("project_id") {
+    config.project_id = Some(project_id.clone());
   }
+  if matches.get_flag("no_cache") {
+    config.cache_enabled = false;
+  }
+
+  let result = match matches.subcommand() {
+    Some(("pipeline", matches)) => return run_pipeline(matches, &config, retry).await,
+    Some(("watch", matches)) => run_watch(matches, &config, retry).await,
+    Some(("digitize", matches)) => run_digitize(matches, &config, retry).await,
+    Some(("classify", matches)) => run_classify(matches, &config, retry).await,
+    Some(("extract", matches)) => run_extract(matches, &config, retry).await,
+    Some(("validate", matches)) => run_validate(matches, &config, retry).await,
+    Some(("discover", matches)) => discover(matches.get_one::<String>("project"), &config, retry).await,
+    _ => unreachable!("subcommand is required"),
+  };
+  result.map(|()| ExitStatus::Success)
 }
 
 #[tokio::main]
 async fn main() {
-  // Define command-line arguments using clap
-  let matches = Command::new("Document Processor")
-    .version("1.0")
-    .author("Your Name")
-    .about("Process documents in a folder")
-    .arg(
-      Arg::new("folder")
-        .long("folder")
-        .value_name("FOLDER")
-        .help("Sets the folder path containing documents to process")
-        .required(true),
-    )
-    .arg(
-      Arg::new("validate_classification")
-        .long("validate-classification")
-        .help("Enables classification validation"),
-    )
-    .arg(
-      Arg::new("validate_extraction")
-        .long("validate-extraction")
-        .help("Enables extraction validation"),
-    )
-    .arg(
-      Arg::new("generative_classification")
-        .long("generative-classification")
-        .help("Enables generative classification"),
-    )
-    .arg(
-      Arg::new("generative_extraction")
-        .long("generative-extraction")
-        .help("Enables generative extraction"),
-    )
-    .get_matches();
-
-  let output_directory_path = "Output Results";
-  let output_directory: PathBuf = PathBuf::from(output_directory_path);
-  let folder_path = matches.get_one::<PathBuf>("folder").expect("required");
-  let validate_classification = matches.get_flag("validate_classification");
-  let validate_extraction = matches.get_flag("validate_extraction");
-  let generative_classification = matches.get_flag("generative_classification");
-  let generative_extraction = matches.get_flag("generative_extraction");
+  let matches = cli::build().try_get_matches().unwrap_or_else(|err| {
+    // Invalid arguments are a configuration error too; --help and --version still exit with 0
+    if err.use_stderr() {
+      let _ = err.print();
+      std::process::exit(ExitStatus::ConfigError as i32);
+    }
+    err.exit()
+  });
 
-  // Call the main processing function with the parsed arguments
-  process_documents_in_folder(
-    folder_path,
-    validate_classification,
-    validate_extraction,
-    generative_classification,
-    generative_extraction,
-    output_directory,
-  )
-  .await
+  let status = match run(&matches).await {
+    Ok(status) => status,
+    Err(err) => {
+      eprintln!("Error: {}", err);
+      ExitStatus::of_error(&err)
+    }
+  };
+  std::process::exit(status as i32);
 }
@@ -0,0 +1,168 @@
+use std::{
+  collections::BTreeMap,
+  fs, io,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::DuError;
+
+/// Folder inside the output folder reserved for bookkeeping: the manifest, stage results and run summaries. Results of
+/// documents are written next to it, so a document named like a bookkeeping file cannot overwrite one.
+pub const DIRECTORY: &str = ".manifest";
+
+// Name of the manifest file inside `DIRECTORY`
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// How far the extraction of one document type has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractionStage {
+  Extracted,
+  Validated,
+  Written,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractionState {
+  pub extractor_id: String,
+  pub stage: ExtractionStage,
+  /// Document type the pages were extracted as.
+  #[serde(default)]
+  pub document_type_id: String,
+  /// First and last page, counted from 1, when the file holds several documents.
+  #[serde(default)]
+  pub pages: Option<(usize, usize)>,
+}
+
+/// Processing state of one input file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentEntry {
+  /// Where the file was last seen.
+  pub path: PathBuf,
+  /// Set as soon as digitization has started, so a crash while waiting does not digitize the file again.
+  pub document_id: Option<String>,
+  pub classified: bool,
+  /// The classification was validated; what it was validated as is stored as a stage result.
+  pub classification_validated: bool,
+  /// Extraction progress per document type ID, or per `p<start>-<end>_<document type ID>` when the file holds several
+  /// documents.
+  pub extractions: BTreeMap<String, ExtractionState>,
+  pub completed: bool,
+}
+
+/// Records which stages each input file has finished, so an interrupted batch can be re-run without repeating them.
+///
+/// Files are keyed by their content hash and their path in the input folder (see [`document_key`]), so copies of the
+/// same content in several places are each processed. The state lives in `.manifest/manifest.json` in the output folder
+/// and is rewritten after every change. Stage results needed by later stages (classification, extraction and
+/// validation results) are kept next to it under `.manifest/<key>/`.
+pub struct Manifest {
+  directory: PathBuf,
+  entries: Mutex<BTreeMap<String, DocumentEntry>>,
+}
+
+impl Manifest {
+  /// Opens the manifest in `output_directory`, starting an empty one if there is none yet.
+  pub fn open(output_directory: &Path) -> Result<Manifest, DuError> {
+    let directory = output_directory.join(DIRECTORY);
+    let manifest_file = directory.join(MANIFEST_FILE);
+    let entries = match fs::read_to_string(&manifest_file) {
+      Ok(contents) => {
+        serde_json::from_str(&contents).map_err(|source| DuError::Deserialize { source, body: contents })?
+      }
+      Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+      Err(err) => return Err(err.into()),
+    };
+    Ok(Manifest {
+      directory,
+      entries: Mutex::new(entries),
+    })
+  }
+
+  /// Returns the recorded state of the file with key `key`.
+  pub fn entry(&self, key: &str) -> DocumentEntry {
+    self.lock().get(key).cloned().unwrap_or_default()
+  }
+
+  /// Applies `change` to the state of `key` and saves the manifest.
+  pub fn update(&self, key: &str, change: impl FnOnce(&mut DocumentEntry)) -> Result<(), DuError> {
+    let mut entries = self.lock();
+    change(entries.entry(key.to_string()).or_default());
+    self.save(&entries)
+  }
+
+  fn save(&self, entries: &BTreeMap<String, DocumentEntry>) -> Result<(), DuError> {
+    // Write to a temporary file first so a crash mid-write never leaves a truncated manifest behind
+    fs::create_dir_all(&self.directory)?;
+    let manifest_file = self.directory.join(MANIFEST_FILE);
+    let temporary_file = manifest_file.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(entries).map_err(io::Error::from)?;
+    fs::write(&temporary_file, json)?;
+    fs::rename(temporary_file, manifest_file)?;
+    Ok(())
+  }
+
+  /// Stores a stage result of `key` under `name` for later runs.
+  pub fn save_result<T: Serialize>(&self, key: &str, name: &str, result: &T) -> Result<(), DuError> {
+    let results_directory = self.directory.join(key);
+    fs::create_dir_all(&results_directory)?;
+    let json = serde_json::to_vec(result).map_err(io::Error::from)?;
+    fs::write(results_directory.join(format!("{}.json", name)), json)?;
+    Ok(())
+  }
+
+  /// Loads a stage result stored with [`Manifest::save_result`].
+  pub fn load_result<T: DeserializeOwned>(&self, key: &str, name: &str) -> Result<T, DuError> {
+    let result_file = self.directory.join(key).join(format!("{}.json", name));
+    let contents = fs::read_to_string(result_file)?;
+    serde_json::from_str(&contents).map_err(|source| DuError::Deserialize { source, body: contents })
+  }
+
+  /// Loads a stage result like [`Manifest::load_result`], or `None` if none was stored under `name`.
+  pub fn load_result_if_saved<T: DeserializeOwned>(&self, key: &str, name: &str) -> Result<Option<T>, DuError> {
+    match self.load_result(key, name) {
+      Ok(result) => Ok(Some(result)),
+      Err(DuError::Io(err)) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, DocumentEntry>> {
+    // A panic while holding the lock cannot leave the map half-updated, so a poisoned lock is still usable
+    self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+}
+
+/// Key of the file with content hash `hash` at `relative_path` in the input folder, e.g. `<hash>/2024/invoice.pdf`.
+pub fn document_key(hash: &str, relative_path: &Path) -> String {
+  let components: Vec<_> = relative_path
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy())
+    .collect();
+  format!("{}/{}", hash, components.join("/"))
+}
+
+/// Hex-encoded SHA-256 of the file at `path`; identifies identical content across files and runs.
+pub fn content_hash(path: &Path) -> Result<String, DuError> {
+  let contents = fs::read(path)?;
+  Ok(hex::encode(Sha256::digest(contents)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn document_keys_join_hash_and_relative_path() {
+    assert_eq!(
+      document_key("abc", Path::new("2024/invoice.pdf")),
+      "abc/2024/invoice.pdf"
+    );
+    assert_eq!(document_key("abc", Path::new("invoice.pdf")), "abc/invoice.pdf");
+  }
+}
@@ -0,0 +1,821 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fs, io,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant, SystemTime},
+};
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+  classify::{ClassificationResult, ClassificationResults},
+  client::DuClient,
+  digitize::DigitizationResult,
+  error::DuError,
+  extract::ExtractionResults,
+  manifest::{self, ExtractionStage, ExtractionState, Manifest},
+  report::{DocumentReport, RunSummary},
+  result_utils::{CSVWriter, JsonWriter, NdjsonWriter, OcrWriter, OutputFormat, XlsxWriter},
+  thresholds::{LowConfidenceClassification, Thresholds},
+  validate::{ValidatedClassification, ValidatedResults},
+};
+
+// Names of the files in the output folder that combine the results of a batch
+const NDJSON_FILE: &str = "results.ndjson";
+const XLSX_FILE: &str = "results.xlsx";
+
+// Folder in the output folder that documents classified below the classification threshold are copied to
+const UNCLASSIFIED_DIRECTORY: &str = "unclassified";
+
+// Settings shared by every document in a run
+pub struct ProcessingOptions {
+  pub validate_classification: bool,
+  pub validate_extraction: bool,
+  pub generative_classification: bool,
+  pub generative_extraction: bool,
+  /// Classifier used for classification and classification validation.
+  pub classifier: String,
+  /// Extractor to use per document type ID. Unmapped types use the generative extractor when generative extraction is
+  /// enabled and otherwise an extractor with the same ID as the document type.
+  pub extractors: HashMap<String, String>,
+  /// Folder the documents were collected from. Results of a document in a subfolder of it are written to the same
+  /// subfolder of `output_directory`, so same-named files in different folders don't overwrite each other.
+  pub input_directory: PathBuf,
+  pub output_directory: PathBuf,
+  /// Folder holding the `<document type>_prompts.json` files used for generative classification and extraction.
+  pub prompts_directory: PathBuf,
+  /// Maximum number of documents processed at the same time; must be at least 1.
+  pub concurrency: usize,
+  /// How long to wait for digitization to finish before giving up on a document.
+  pub digitization_timeout: Duration,
+  /// Write the digitization DOM and text next to the extraction results.
+  pub save_ocr: bool,
+  /// Joins the values of multi-valued fields and table cells in the CSV output.
+  pub multi_value_separator: String,
+  /// Formats the results are written in.
+  pub formats: Vec<OutputFormat>,
+  /// Decide which classifications and extractions are validated and which values are highlighted in the Excel output.
+  pub thresholds: Thresholds,
+  /// What to do with documents classified below the classification threshold.
+  pub low_confidence_classification: LowConfidenceClassification,
+  /// Start no further documents once one has failed. Documents already in flight still finish.
+  pub fail_fast: bool,
+}
+
+/// Runs documents through digitization, classification, extraction and the optional validation steps.
+///
+/// Progress is recorded in a [`Manifest`] in the output folder; documents that were already processed are skipped and
+/// interrupted ones continue with the first unfinished stage. Cloning is cheap; clones share the API client, options
+/// and manifest.
+#[derive(Clone)]
+pub struct Pipeline {
+  client: DuClient,
+  options: Arc<ProcessingOptions>,
+  classification_prompts: Option<Value>,
+  manifest: Arc<Manifest>,
+  // One lock per content hash, held while a document is uploaded so identical files share one upload through the
+  // digitization cache
+  digitizations: Arc<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+  // Combined results.ndjson of the run, if that format is selected
+  ndjson_writer: Option<Arc<NdjsonWriter>>,
+  // Results collected for results.xlsx, if that format is selected
+  xlsx_writer: Option<Arc<XlsxWriter>>,
+}
+
+// One document type found in a file. When the classifier found several documents in one file, each is a segment of
+// its own with the pages it covers, extracted from those pages only and written to its own result files.
+struct Segment {
+  document_type_id: String,
+  // First and last page, counted from 1, when the file is split
+  pages: Option<(usize, usize)>,
+}
+
+impl Segment {
+  // Key of the segment in the manifest and the stored results, e.g. `invoices` or `p1-2_invoices`
+  fn key(&self) -> String {
+    match self.page_range() {
+      Some(pages) => format!("p{}_{}", pages, self.document_type_id),
+      None => self.document_type_id.clone(),
+    }
+  }
+
+  // Page range as sent to extraction, e.g. `1-2`
+  fn page_range(&self) -> Option<String> {
+    self.pages.map(|(first, last)| format!("{}-{}", first, last))
+  }
+
+  // Path the result files are named after: `<stem>_p<start>-<end>_<type>.<ext>` next to the file when it is split
+  fn result_path(&self, path: &Path) -> PathBuf {
+    if self.pages.is_none() {
+      return path.to_path_buf();
+    }
+    let mut file_name = format!(
+      "{}_{}",
+      path.file_stem().unwrap_or_default().to_string_lossy(),
+      self.key()
+    );
+    if let Some(extension) = path.extension() {
+      file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+  }
+
+  // How the segment is named in progress messages
+  fn description(&self) -> String {
+    match self.page_range() {
+      Some(pages) => format!("{} on pages {}", self.document_type_id, pages),
+      None => self.document_type_id.clone(),
+    }
+  }
+}
+
+impl Pipeline {
+  pub fn new(client: DuClient, options: ProcessingOptions) -> Result<Pipeline, DuError> {
+    // Load classification prompts if generative_classification is enabled
+    let classification_prompts = if options.generative_classification {
+      Some(load_prompts(&options.prompts_directory, "classification")?)
+    } else {
+      None
+    };
+
+    let manifest = Manifest::open(&options.output_directory)?;
+    let ndjson_writer = if options.formats.contains(&OutputFormat::Ndjson) {
+      Some(Arc::new(NdjsonWriter::open(
+        &options.output_directory.join(NDJSON_FILE),
+      )?))
+    } else {
+      None
+    };
+    let xlsx_writer = options.formats.contains(&OutputFormat::Xlsx).then(|| {
+      Arc::new(XlsxWriter::new(
+        options.thresholds.clone(),
+        &options.multi_value_separator,
+      ))
+    });
+
+    Ok(Pipeline {
+      client,
+      options: Arc::new(options),
+      classification_prompts,
+      manifest: Arc::new(manifest),
+      digitizations: Arc::default(),
+      ndjson_writer,
+      xlsx_writer,
+    })
+  }
+
+  /// Processes `documents` with up to `concurrency` documents in flight at once and returns the outcome of each, in
+  /// the order they finished.
+  ///
+  /// A failing document is reported and skipped; it never cancels the others, but with `fail_fast` no further documents
+  /// are started. Documents that were not started and documents whose task panicked have no outcome, but are counted
+  /// as failed in the [`RunSummary`] that is written to the output folder and printed once all are done.
+  pub async fn run(&self, documents: Vec<PathBuf>) -> Vec<(PathBuf, Result<(), DuError>)> {
+    let started_at = SystemTime::now();
+    let started = Instant::now();
+    let permits = Arc::new(Semaphore::new(self.options.concurrency));
+    let mut tasks = JoinSet::new();
+    let failed = Arc::new(AtomicBool::new(false));
+    for path in documents.iter().cloned() {
+      // Waiting for a permit before spawning keeps at most `concurrency` documents in flight
+      let permit = permits
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("semaphore is never closed");
+      let pipeline = self.for_document(&path);
+      let failed = failed.clone();
+      tasks.spawn(async move {
+        let _permit = permit;
+        if pipeline.options.fail_fast && failed.load(Ordering::SeqCst) {
+          return (path, None);
+        }
+        let started = Instant::now();
+        let mut report = DocumentReport::new(&path);
+        let result = pipeline.process_document(&path, &mut report).await;
+        report.duration_secs = started.elapsed().as_secs_f64();
+        report.error = result.as_ref().err().map(ToString::to_string);
+        if result.is_err() {
+          failed.store(true, Ordering::SeqCst);
+        }
+        (path, Some((result, report)))
+      });
+    }
+
+    let mut outcomes = Vec::new();
+    let mut reports = Vec::new();
+    let mut not_started = 0;
+    while let Some(joined) = tasks.join_next().await {
+      match joined {
+        Ok((path, None)) => {
+          not_started += 1;
+          reports.push(DocumentReport {
+            error: Some("not started after an earlier document failed (--fail-fast)".to_string()),
+            ..DocumentReport::new(&path)
+          });
+        }
+        Ok((path, Some((result, report)))) => {
+          match &result {
+            Ok(()) => println!("[{}] Done", path.display()),
+            Err(err) => eprintln!("[{}] Error processing document: {}", path.display(), err),
+          }
+          outcomes.push((path, result));
+          reports.push(report);
+        }
+        Err(err) => eprintln!("Document task failed: {}", err),
+      }
+    }
+    // A task that panicked returned no report, so its document is the one without one
+    let reported: HashSet<&PathBuf> = reports.iter().map(|report| &report.path).collect();
+    let panicked: Vec<DocumentReport> = documents
+      .iter()
+      .filter(|path| !reported.contains(path))
+      .map(|path| DocumentReport {
+        error: Some("document task panicked".to_string()),
+        ..DocumentReport::new(path)
+      })
+      .collect();
+    reports.extend(panicked);
+    if not_started > 0 {
+      eprintln!(
+        "Stopped after a failed document (--fail-fast), {} documents were not processed",
+        not_started
+      );
+    }
+
+    // The workbook holds every document this pipeline has processed, so in watch mode it grows with each batch
+    if let Some(xlsx_writer) = self.xlsx_writer.as_ref().filter(|writer| !writer.is_empty()) {
+      let workbook = self.options.output_directory.join(XLSX_FILE);
+      match xlsx_writer.save(&workbook) {
+        Ok(()) => println!("Results workbook written to {}", workbook.display()),
+        Err(err) => eprintln!("Error writing results workbook {}: {}", workbook.display(), err),
+      }
+    }
+
+    let summary = RunSummary::new(started_at, started.elapsed(), reports);
+    if let Err(err) = summary.write(&self.options.output_directory) {
+      eprintln!("Error writing run summary: {}", err);
+    }
+    print!("{}", summary.condensed());
+    outcomes
+  }
+
+  // A clone whose API requests label their retry messages with the document's path
+  fn for_document(&self, path: &Path) -> Pipeline {
+    Pipeline {
+      client: self.client.clone().with_label(&path.display().to_string()),
+      ..self.clone()
+    }
+  }
+
+  // Picks the extractor for a document type
+  fn extractor_for<'a>(&'a self, document_type_id: &'a str) -> &'a str {
+    match self.options.extractors.get(document_type_id) {
+      Some(extractor_id) => extractor_id,
+      None if self.options.generative_extraction => "generative_extractor",
+      None => document_type_id,
+    }
+  }
+
+  // Mirrors the subfolder of `path` in the input folder below the output folder
+  fn output_directory_for(&self, path: &Path) -> PathBuf {
+    self.options.output_directory.join(self.subfolder_of(path))
+  }
+
+  // Mirrors the subfolder of `path` in the input folder below the unclassified folder
+  fn unclassified_directory_for(&self, path: &Path) -> PathBuf {
+    self
+      .options
+      .output_directory
+      .join(UNCLASSIFIED_DIRECTORY)
+      .join(self.subfolder_of(path))
+  }
+
+  // Path of a document relative to the input folder, or just its file name if it is not inside it
+  fn relative_path_of<'a>(&self, path: &'a Path) -> &'a Path {
+    path
+      .strip_prefix(&self.options.input_directory)
+      .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()))
+  }
+
+  // Waits until no other document with content hash `hash` is being uploaded
+  async fn digitization_lock(&self, hash: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = self
+      .digitizations
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .entry(hash.to_string())
+      .or_default()
+      .clone();
+    lock.lock_owned().await
+  }
+
+  fn subfolder_of<'a>(&self, path: &'a Path) -> &'a Path {
+    path
+      .strip_prefix(&self.options.input_directory)
+      .ok()
+      .and_then(Path::parent)
+      .unwrap_or(Path::new(""))
+  }
+
+  // Runs a single document through the whole pipeline, skipping the stages the manifest records as finished
+  async fn process_document(&self, path: &PathBuf, report: &mut DocumentReport) -> Result<(), DuError> {
+    let label = path.display();
+    let options = &self.options;
+    let output_directory = &self.output_directory_for(path);
+
+    let hash = manifest::content_hash(path)?;
+    let key = &manifest::document_key(&hash, self.relative_path_of(path));
+    let entry = self.manifest.entry(key);
+    if entry.completed {
+      println!("[{}] Already processed, skipping", label);
+      report.skipped = true;
+      for state in entry.extractions.values() {
+        let segment = Segment {
+          document_type_id: state.document_type_id.clone(),
+          pages: state.pages,
+        };
+        self.add_written_results(path, key, &segment)?;
+      }
+      return Ok(());
+    }
+    self.manifest.update(key, |entry| entry.path = path.clone())?;
+
+    let (document_id, digitization_result) = self.digitize(path, key, &hash, entry.document_id).await?;
+    println!("[{}] Digitized", label);
+    report.digitized = true;
+    if options.save_ocr {
+      if let Some(digitization_output) = &digitization_result.result {
+        // The typed DOM only serves the page and word counts; the raw DOM is saved even when it doesn't parse
+        match digitization_output.parse_document_object_model() {
+          Ok(Some(dom)) => println!(
+            "[{}] Saving OCR output: {} pages, {} words",
+            label,
+            dom.pages.len(),
+            dom.words().count()
+          ),
+          Ok(None) => {}
+          Err(err) => eprintln!("[{}] Saving OCR output, DOM could not be read: {}", label, err),
+        }
+        OcrWriter::write_digitization_results(digitization_output, path, output_directory)?;
+      }
+    }
+
+    let classification_results: ClassificationResults = if entry.classified {
+      self.manifest.load_result(key, "classification")?
+    } else {
+      let classification_results = self
+        .client
+        .classify(&document_id, &options.classifier, self.classification_prompts.clone())
+        .await?;
+      self
+        .manifest
+        .save_result(key, "classification", &classification_results)?;
+      self.manifest.update(key, |entry| entry.classified = true)?;
+      classification_results
+    };
+    report.classified = true;
+    if classification_results.classification_results.is_empty() {
+      println!("[{}] No known document type found", label);
+    }
+    // Several results mean the file holds several documents, each extracted from its own pages
+    let split = classification_results.classification_results.len() > 1;
+    let segment_of = |result: &ClassificationResult| Segment {
+      document_type_id: result.document_type_id.clone(),
+      pages: split.then(|| result.document_bounds.pages()),
+    };
+    for result in &classification_results.classification_results {
+      let segment = segment_of(result);
+      println!(
+        "[{}] Classified {} (confidence {})",
+        label,
+        segment.description(),
+        result.confidence
+      );
+      report
+        .document_type(&segment.document_type_id, segment.page_range().as_deref())
+        .classification_confidence = Some(result.confidence);
+    }
+
+    // Document types classified below the threshold are not extracted as is; neither are documents the classifier
+    // found nothing in, once a threshold asks for a closer look
+    let threshold = options.thresholds.classification_confidence;
+    let (confident, uncertain): (Vec<_>, Vec<_>) = classification_results
+      .classification_results
+      .iter()
+      .partition(|result| threshold.is_none_or(|threshold| result.confidence >= f64::from(threshold)));
+    for result in &uncertain {
+      println!(
+        "[{}] Classification of {} is below the threshold of {}",
+        label,
+        segment_of(result).description(),
+        threshold.unwrap_or_default()
+      );
+    }
+    let needs_review = threshold.is_some() && (!uncertain.is_empty() || confident.is_empty());
+
+    if options.validate_classification
+      || (needs_review && options.low_confidence_classification == LowConfidenceClassification::Validate)
+    {
+      let validated: Vec<ValidatedClassification> = if entry.classification_validated {
+        self.manifest.load_result(key, "classification_validation")?
+      } else {
+        println!("[{}] Sending classification to validation", label);
+        let validated = self
+          .client
+          .validate_classification(&options.classifier, &document_id, &classification_results)
+          .await?;
+        self
+          .manifest
+          .save_result(key, "classification_validation", &validated)?;
+        self
+          .manifest
+          .update(key, |entry| entry.classification_validated = true)?;
+        validated
+      };
+      // The operator may have split, merged or retyped the documents, so the validated ones replace the classified ones
+      let split = validated.len() > 1;
+      let segments: Vec<Segment> = validated
+        .into_iter()
+        .map(|validated| Segment {
+          document_type_id: validated.document_type_id,
+          pages: validated.pages.filter(|_| split),
+        })
+        .collect();
+      let descriptions: Vec<String> = segments.iter().map(Segment::description).collect();
+      println!("[{}] Classification validated as {}", label, descriptions.join(", "));
+      report.document_types.retain(|report| {
+        segments
+          .iter()
+          .any(|segment| report.document_type_id == segment.document_type_id && report.pages == segment.page_range())
+      });
+      for segment in &segments {
+        self.extract_segment(path, key, &document_id, segment, report).await?;
+        if options.formats.contains(&OutputFormat::Csv) {
+          CSVWriter::print_csv_results(&segment.result_path(path), output_directory)?;
+        }
+      }
+    } else {
+      for result in &confident {
+        self
+          .extract_segment(path, key, &document_id, &segment_of(result), report)
+          .await?;
+      }
+      if needs_review {
+        for result in &uncertain {
+          let segment = segment_of(result);
+          report
+            .document_type(&segment.document_type_id, segment.page_range().as_deref())
+            .below_classification_threshold = true;
+        }
+        let copy = copy_to_unclassified(path, &classification_results, &self.unclassified_directory_for(path))?;
+        println!("[{}] Copied to {}", label, copy.display());
+        report.unclassified = true;
+      }
+    }
+
+    self.manifest.update(key, |entry| entry.completed = true)?;
+    Ok(())
+  }
+
+  // Extracts one segment of a document, validates the extraction if enabled and writes the results, resuming after the
+  // last stage the manifest records for that segment
+  async fn extract_segment(
+    &self,
+    path: &Path,
+    manifest_key: &str,
+    document_id: &str,
+    segment: &Segment,
+    report: &mut DocumentReport,
+  ) -> Result<(), DuError> {
+    let label = path.display();
+    let options = &self.options;
+    let document_type_id = segment.document_type_id.as_str();
+    let page_range = segment.page_range();
+    let key = segment.key();
+    let extraction_name = format!("{}_extraction", key);
+    let validation_name = format!("{}_validation", key);
+
+    let state = self.manifest.entry(manifest_key).extractions.get(&key).cloned();
+    let stage = state.as_ref().map(|state| state.stage);
+    if stage == Some(ExtractionStage::Written) {
+      println!(
+        "[{}] Results for {} already written, skipping",
+        label,
+        segment.description()
+      );
+      return self.add_written_results(path, manifest_key, segment);
+    }
+    let extractor_id = match &state {
+      Some(state) => state.extractor_id.clone(),
+      None => self.extractor_for(document_type_id).to_string(),
+    };
+    let record_stage = |stage| {
+      self.manifest.update(manifest_key, |entry| {
+        entry.extractions.insert(
+          key.clone(),
+          ExtractionState {
+            extractor_id: extractor_id.clone(),
+            stage,
+            document_type_id: segment.document_type_id.clone(),
+            pages: segment.pages,
+          },
+        );
+      })
+    };
+
+    let extraction_results: ExtractionResults = if stage.is_some() {
+      self.manifest.load_result(manifest_key, &extraction_name)?
+    } else {
+      let extraction_prompts = if options.generative_extraction {
+        Some(load_prompts(&options.prompts_directory, document_type_id)?)
+      } else {
+        None
+      };
+      let extraction_results = self
+        .client
+        .extract(&extractor_id, document_id, page_range.as_deref(), extraction_prompts)
+        .await?;
+      self
+        .manifest
+        .save_result(manifest_key, &extraction_name, &extraction_results)?;
+      record_stage(ExtractionStage::Extracted)?;
+      println!("[{}] Extracted {} with {}", label, segment.description(), extractor_id);
+      extraction_results
+    };
+
+    report.extracted(document_type_id, page_range.as_deref(), &extraction_results);
+
+    let validated_results = if !options.validate_extraction {
+      None
+    } else if stage == Some(ExtractionStage::Validated) {
+      Some(self.manifest.load_result(manifest_key, &validation_name)?)
+    } else if !self.needs_validation(path, segment, &extraction_results, report) {
+      None
+    } else {
+      println!(
+        "[{}] Sending extraction of {} to validation",
+        label,
+        segment.description()
+      );
+      let validated_results = self
+        .client
+        .validate_extraction(&extractor_id, document_id, &extraction_results)
+        .await?;
+      self
+        .manifest
+        .save_result(manifest_key, &validation_name, &validated_results)?;
+      record_stage(ExtractionStage::Validated)?;
+      Some(validated_results)
+    };
+    report.document_type(document_type_id, page_range.as_deref()).validated = validated_results.is_some();
+    self.write_results(
+      path,
+      segment,
+      &extractor_id,
+      &extraction_results,
+      validated_results.as_ref(),
+    )?;
+    record_stage(ExtractionStage::Written)
+  }
+
+  // Adds the results of a segment written by an earlier run to the workbook, since every run writes it from scratch
+  fn add_written_results(&self, path: &Path, manifest_key: &str, segment: &Segment) -> Result<(), DuError> {
+    let Some(xlsx_writer) = &self.xlsx_writer else {
+      return Ok(());
+    };
+    let key = segment.key();
+    let extraction_results: ExtractionResults = self
+      .manifest
+      .load_result(manifest_key, &format!("{}_extraction", key))?;
+    let validated_results: Option<ValidatedResults> = self
+      .manifest
+      .load_result_if_saved(manifest_key, &format!("{}_validation", key))?;
+    xlsx_writer.add(
+      path,
+      segment.page_range().as_deref(),
+      &extraction_results,
+      validated_results.as_ref(),
+    );
+    Ok(())
+  }
+
+  // Digitizes a document, or waits for the digitization of `document_id` when resuming. A document resumed from an
+  // earlier run or reused from the digitization cache may be gone or failed by now, so a lost document is uploaded
+  // once more before giving up.
+  async fn digitize(
+    &self,
+    path: &Path,
+    key: &str,
+    hash: &str,
+    mut document_id: Option<String>,
+  ) -> Result<(String, DigitizationResult), DuError> {
+    let label = path.display();
+    for attempt in 1..=2 {
+      let current_id = match document_id.take() {
+        Some(document_id) => {
+          println!("[{}] Resuming, document ID: {}", label, document_id);
+          document_id
+        }
+        None => {
+          // Identical files uploaded one at a time find each other's document in the digitization cache
+          let _uploading = self.digitization_lock(hash).await;
+          println!("[{}] Processing document", label);
+          let document_id = self.client.digitize(&path.to_path_buf()).await?;
+          self
+            .manifest
+            .update(key, |entry| entry.document_id = Some(document_id.clone()))?;
+          println!("[{}] Digitization started, document ID: {}", label, document_id);
+          document_id
+        }
+      };
+      // Fetching the result of a finished digitization is free, so this also runs when resuming
+      match self
+        .client
+        .wait_for_digitization(&current_id, self.options.digitization_timeout)
+        .await
+      {
+        Ok(digitization_result) => return Ok((current_id, digitization_result)),
+        Err(err) if digitization_is_lost(&err) => {
+          self.manifest.update(key, |entry| entry.document_id = None)?;
+          if attempt == 2 {
+            return Err(err);
+          }
+          eprintln!("[{}] Document {} is lost ({}), uploading again", label, current_id, err);
+        }
+        Err(err) => return Err(err),
+      }
+    }
+    unreachable!("the last attempt returns")
+  }
+
+  // Whether an extraction has to be validated: always without extraction thresholds, otherwise only if a field has a
+  // value below its threshold. Those fields are recorded in `report`.
+  fn needs_validation(
+    &self,
+    path: &Path,
+    segment: &Segment,
+    extraction_results: &ExtractionResults,
+    report: &mut DocumentReport,
+  ) -> bool {
+    let thresholds = &self.options.thresholds;
+    if !thresholds.routes_extraction() {
+      return true;
+    }
+    let page_range = segment.page_range();
+    let segment_report = report.document_type(&segment.document_type_id, page_range.as_deref());
+    let low_confidence_fields = thresholds.low_confidence_fields(&segment.document_type_id, extraction_results);
+    if low_confidence_fields.is_empty() {
+      println!(
+        "[{}] Every field of {} clears its threshold, skipping validation",
+        path.display(),
+        segment.description()
+      );
+      segment_report.auto_accepted = true;
+      return false;
+    }
+    let field_names: Vec<&str> = low_confidence_fields
+      .iter()
+      .map(|field| field.field_name.as_str())
+      .collect();
+    println!(
+      "[{}] Validating {}, below threshold: {}",
+      path.display(),
+      segment.description(),
+      field_names.join(", ")
+    );
+    segment_report.low_confidence_fields = low_confidence_fields;
+    true
+  }
+
+  // Writes the results of one document type in every selected format
+  fn write_results(
+    &self,
+    path: &Path,
+    segment: &Segment,
+    extractor_id: &str,
+    extraction_results: &ExtractionResults,
+    validated_results: Option<&ValidatedResults>,
+  ) -> Result<(), DuError> {
+    let options = &self.options;
+    let output_directory = &self.output_directory_for(path);
+    // Per-document files are named after the segment, combined outputs refer to the file and its pages
+    let result_path = &segment.result_path(path);
+    let page_range = segment.page_range();
+    for format in &options.formats {
+      match format {
+        OutputFormat::Csv => match validated_results {
+          Some(validated_results) => CSVWriter::write_validated_results_to_csv(
+            validated_results,
+            extraction_results,
+            result_path,
+            output_directory,
+            &options.multi_value_separator,
+          )?,
+          None => CSVWriter::write_extraction_results_to_csv(
+            extraction_results,
+            result_path,
+            output_directory,
+            &options.multi_value_separator,
+          )?,
+        },
+        OutputFormat::Json => {
+          JsonWriter::write_results(extraction_results, validated_results, result_path, output_directory)?
+        }
+        OutputFormat::Ndjson => {
+          if let Some(ndjson_writer) = &self.ndjson_writer {
+            ndjson_writer.append(
+              path,
+              page_range.as_deref(),
+              extractor_id,
+              extraction_results,
+              validated_results,
+            )?;
+          }
+        }
+        OutputFormat::Xlsx => {
+          if let Some(xlsx_writer) = &self.xlsx_writer {
+            xlsx_writer.add(path, page_range.as_deref(), extraction_results, validated_results);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Whether waiting for a digitization failed because the digitization failed or its document is gone
+fn digitization_is_lost(err: &DuError) -> bool {
+  match err {
+    DuError::UnexpectedResponse(_) => true,
+    DuError::Http { status, .. } => *status == StatusCode::NOT_FOUND || *status == StatusCode::GONE,
+    _ => false,
+  }
+}
+
+// Copies a document that could not be classified confidently to `directory`, together with its classification results
+// in `<stem>_classification.json` so a reviewer sees what the classifier found, and returns the path of the copy
+fn copy_to_unclassified(
+  path: &Path,
+  classification_results: &ClassificationResults,
+  directory: &Path,
+) -> Result<PathBuf, DuError> {
+  fs::create_dir_all(directory)?;
+  let copy = directory.join(path.file_name().unwrap_or_default());
+  fs::copy(path, &copy)?;
+  let file_name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+  let json = serde_json::to_vec_pretty(classification_results).map_err(io::Error::from)?;
+  fs::write(directory.join(format!("{}_classification.json", file_name)), json)?;
+  Ok(copy)
+}
+
+// Function to load prompts from a JSON file based on the document type ID
+fn load_prompts(prompts_directory: &Path, document_type_id: &str) -> Result<Value, DuError> {
+  let prompts_file = prompts_directory.join(format!("{}_prompts.json", document_type_id));
+  let contents = std::fs::read_to_string(prompts_file)?;
+  serde_json::from_str(&contents).map_err(|source| DuError::Deserialize { source, body: contents })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn segment(pages: Option<(usize, usize)>) -> Segment {
+    Segment {
+      document_type_id: "invoices".to_string(),
+      pages,
+    }
+  }
+
+  #[test]
+  fn whole_files_keep_their_names() {
+    let segment = segment(None);
+    assert_eq!(segment.key(), "invoices");
+    assert_eq!(segment.page_range(), None);
+    assert_eq!(segment.description(), "invoices");
+    let path = Path::new("in/2024/scan.pdf");
+    assert_eq!(segment.result_path(path), path);
+  }
+
+  #[test]
+  fn split_files_are_named_per_segment() {
+    let segment = segment(Some((1, 2)));
+    assert_eq!(segment.key(), "p1-2_invoices");
+    assert_eq!(segment.page_range().as_deref(), Some("1-2"));
+    assert_eq!(segment.description(), "invoices on pages 1-2");
+    assert_eq!(
+      segment.result_path(Path::new("in/2024/scan.pdf")),
+      Path::new("in/2024/scan_p1-2_invoices.pdf")
+    );
+    assert_eq!(
+      segment.result_path(Path::new("in/scan")),
+      Path::new("in/scan_p1-2_invoices")
+    );
+  }
+}
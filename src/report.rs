@@ -0,0 +1,302 @@
+use std::{
+  collections::BTreeMap,
+  fmt::Write as _,
+  fs, io,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+
+use crate::{error::DuError, extract::ExtractionResults, manifest, thresholds::LowConfidenceField};
+
+// Names of the summary files in the bookkeeping folder of the output folder
+const JSON_FILE: &str = "summary.json";
+const TEXT_FILE: &str = "summary.txt";
+
+/// What happened to one document during a run.
+#[derive(Debug, Default, Serialize)]
+pub struct DocumentReport {
+  pub path: PathBuf,
+  pub duration_secs: f64,
+  /// Finished in an earlier run, so nothing was done.
+  pub skipped: bool,
+  pub digitized: bool,
+  pub classified: bool,
+  /// Copied to the unclassified folder because it was classified below the classification threshold.
+  pub unclassified: bool,
+  pub document_types: Vec<DocumentTypeReport>,
+  /// Why processing stopped, if it failed.
+  pub error: Option<String>,
+}
+
+/// One document type found in a document.
+#[derive(Debug, Default, Serialize)]
+pub struct DocumentTypeReport {
+  pub document_type_id: String,
+  /// Pages of the file holding this document, e.g. `1-2`, when the file holds several documents.
+  pub pages: Option<String>,
+  /// Missing when the type was assigned during classification validation rather than by the classifier.
+  pub classification_confidence: Option<f64>,
+  /// Average confidence of the extracted field values.
+  pub extraction_confidence: Option<f64>,
+  /// Not extracted because the classification confidence was below the threshold.
+  pub below_classification_threshold: bool,
+  pub extracted: bool,
+  pub validated: bool,
+  /// Validation was skipped because every field cleared its confidence threshold.
+  pub auto_accepted: bool,
+  /// Fields whose confidence sent the extraction to validation.
+  pub low_confidence_fields: Vec<LowConfidenceField>,
+}
+
+impl DocumentReport {
+  pub fn new(path: &Path) -> DocumentReport {
+    DocumentReport {
+      path: path.to_path_buf(),
+      ..DocumentReport::default()
+    }
+  }
+
+  /// Returns the report of `document_type_id` on `pages`, adding it if the document has none yet.
+  pub fn document_type(&mut self, document_type_id: &str, pages: Option<&str>) -> &mut DocumentTypeReport {
+    let index = match self
+      .document_types
+      .iter()
+      .position(|report| report.document_type_id == document_type_id && report.pages.as_deref() == pages)
+    {
+      Some(index) => index,
+      None => {
+        self.document_types.push(DocumentTypeReport {
+          document_type_id: document_type_id.to_string(),
+          pages: pages.map(str::to_string),
+          ..DocumentTypeReport::default()
+        });
+        self.document_types.len() - 1
+      }
+    };
+    &mut self.document_types[index]
+  }
+
+  /// Records the extraction of `document_type_id` on `pages` and the average confidence of its values.
+  pub fn extracted(&mut self, document_type_id: &str, pages: Option<&str>, extraction_results: &ExtractionResults) {
+    let confidences: Vec<f64> = extraction_results
+      .results_document
+      .fields
+      .iter()
+      .flatten()
+      .flat_map(|field| &field.values)
+      .map(|value| value.confidence)
+      .collect();
+    let report = self.document_type(document_type_id, pages);
+    report.extracted = true;
+    report.extraction_confidence = average(&confidences);
+  }
+}
+
+/// Document and confidence figures of one document type across a run.
+#[derive(Debug, Default, Serialize)]
+pub struct DocumentTypeSummary {
+  pub documents: usize,
+  pub average_classification_confidence: Option<f64>,
+  pub average_extraction_confidence: Option<f64>,
+}
+
+/// Number of documents that got through each stage.
+#[derive(Debug, Default, Serialize)]
+pub struct StageCounts {
+  pub digitized: usize,
+  pub classified: usize,
+  pub extracted: usize,
+  pub validated: usize,
+  /// Extracted with validation enabled but accepted without it, as every field cleared its threshold.
+  pub auto_accepted: usize,
+  pub unclassified: usize,
+  pub failed: usize,
+  pub skipped: usize,
+}
+
+/// Summary of a run over a batch of documents, written to `.manifest/summary.json` and `.manifest/summary.txt` in the
+/// output folder.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+  pub started_at: String,
+  pub duration_secs: f64,
+  pub document_count: usize,
+  pub stages: StageCounts,
+  pub document_types: BTreeMap<String, DocumentTypeSummary>,
+  pub documents: Vec<DocumentReport>,
+}
+
+impl RunSummary {
+  pub fn new(started_at: SystemTime, duration: Duration, mut documents: Vec<DocumentReport>) -> RunSummary {
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut stages = StageCounts::default();
+    let mut confidences: BTreeMap<&str, (usize, Vec<f64>, Vec<f64>)> = BTreeMap::new();
+    for document in &documents {
+      let count = |done: bool| usize::from(done);
+      stages.digitized += count(document.digitized);
+      stages.classified += count(document.classified);
+      stages.extracted += count(document.document_types.iter().any(|report| report.extracted));
+      stages.validated += count(document.document_types.iter().any(|report| report.validated));
+      stages.auto_accepted +=
+        count(!document.document_types.is_empty() && document.document_types.iter().all(|report| report.auto_accepted));
+      stages.unclassified += count(document.unclassified);
+      stages.failed += count(document.error.is_some());
+      stages.skipped += count(document.skipped);
+      for report in &document.document_types {
+        let (documents, classification, extraction) = confidences.entry(&report.document_type_id).or_default();
+        *documents += 1;
+        classification.extend(report.classification_confidence);
+        extraction.extend(report.extraction_confidence);
+      }
+    }
+    let document_types = confidences
+      .into_iter()
+      .map(|(document_type_id, (documents, classification, extraction))| {
+        (
+          document_type_id.to_string(),
+          DocumentTypeSummary {
+            documents,
+            average_classification_confidence: average(&classification),
+            average_extraction_confidence: average(&extraction),
+          },
+        )
+      })
+      .collect();
+
+    RunSummary {
+      started_at: httpdate::fmt_http_date(started_at),
+      duration_secs: duration.as_secs_f64(),
+      document_count: documents.len(),
+      stages,
+      document_types,
+      documents,
+    }
+  }
+
+  /// Writes the summary as `summary.json` and `summary.txt` to the `.manifest` folder of `output_directory`, replacing
+  /// earlier ones.
+  pub fn write(&self, output_directory: &Path) -> Result<(), DuError> {
+    let directory = output_directory.join(manifest::DIRECTORY);
+    fs::create_dir_all(&directory)?;
+    let json = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+    fs::write(directory.join(JSON_FILE), json)?;
+    fs::write(directory.join(TEXT_FILE), self.text())?;
+    Ok(())
+  }
+
+  /// A few lines with the stage counts, document types and failures, for the console.
+  pub fn condensed(&self) -> String {
+    let mut text = self.totals();
+    if !self.document_types.is_empty() {
+      let document_types: Vec<String> = self
+        .document_types
+        .iter()
+        .map(|(document_type_id, summary)| format!("{} {}", document_type_id, summary.documents))
+        .collect();
+      let _ = writeln!(text, "Document types: {}", document_types.join(", "));
+    }
+    for document in &self.documents {
+      if let Some(error) = &document.error {
+        let _ = writeln!(text, "Failed: {}: {}", document.path.display(), error);
+      }
+    }
+    text
+  }
+
+  // The full report: totals, a line per document type and per document
+  fn text(&self) -> String {
+    let mut text = format!("Run started {}\n", self.started_at);
+    text.push_str(&self.totals());
+
+    let _ = writeln!(text, "\nDocument types:");
+    for (document_type_id, summary) in &self.document_types {
+      let _ = writeln!(
+        text,
+        "  {}: {} documents, classification confidence {}, extraction confidence {}",
+        document_type_id,
+        summary.documents,
+        percentage(summary.average_classification_confidence),
+        percentage(summary.average_extraction_confidence)
+      );
+    }
+
+    let _ = writeln!(text, "\nDocuments:");
+    for document in &self.documents {
+      let status = if let Some(error) = &document.error {
+        format!("failed: {}", error)
+      } else if document.skipped {
+        "skipped, already processed".to_string()
+      } else {
+        let document_types: Vec<String> = document
+          .document_types
+          .iter()
+          .map(|report| {
+            let stage = if report.validated && !report.low_confidence_fields.is_empty() {
+              let field_names: Vec<&str> = report
+                .low_confidence_fields
+                .iter()
+                .map(|field| field.field_name.as_str())
+                .collect();
+              format!("validated, below threshold: {}", field_names.join(", "))
+            } else if report.validated {
+              "validated".to_string()
+            } else if report.auto_accepted {
+              "accepted above thresholds".to_string()
+            } else if report.extracted {
+              "extracted".to_string()
+            } else if report.below_classification_threshold {
+              "unclassified, confidence below threshold".to_string()
+            } else {
+              "classified".to_string()
+            };
+            match &report.pages {
+              Some(pages) => format!("{} on pages {} ({})", report.document_type_id, pages, stage),
+              None => format!("{} ({})", report.document_type_id, stage),
+            }
+          })
+          .collect();
+        document_types.join(", ")
+      };
+      let _ = writeln!(
+        text,
+        "  {} [{:.1}s]: {}",
+        document.path.display(),
+        document.duration_secs,
+        status
+      );
+    }
+    text
+  }
+
+  fn totals(&self) -> String {
+    let stages = &self.stages;
+    format!(
+      "{} documents in {:.1}s: {} digitized, {} classified, {} extracted, {} validated, {} auto-accepted, \
+       {} unclassified, {} failed, {} skipped\n",
+      self.document_count,
+      self.duration_secs,
+      stages.digitized,
+      stages.classified,
+      stages.extracted,
+      stages.validated,
+      stages.auto_accepted,
+      stages.unclassified,
+      stages.failed,
+      stages.skipped
+    )
+  }
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+  (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn percentage(value: Option<f64>) -> String {
+  match value {
+    Some(value) => format!("{:.1}%", value * 100.0),
+    None => "n/a".to_string(),
+  }
+}
@@ -1,157 +1,341 @@
 use std::{
+  collections::{BTreeMap, HashMap, HashSet},
   fs,
-  fs::File,
+  fs::{File, OpenOptions},
   io,
-  io::BufReader,
+  io::{BufReader, Write},
   path::{Path, PathBuf},
+  str::FromStr,
+  sync::Mutex,
 };
 
 use csv::{Position, ReaderBuilder, Writer};
+use rust_xlsxwriter::{ColNum, Color, Format, RowNum, Workbook, Worksheet};
+use serde::{Deserialize, Serialize};
 
-use crate::{extract::ExtractionResults, validate::ValidatedResults};
+use crate::{
+  digitize::DigitizationOutput,
+  error::DuError,
+  extract::{ColumnInfo, ExtractionResults, Field, FieldValue, ResultsDocument, Table, TableValue},
+  thresholds::Thresholds,
+  validate::ValidatedResults,
+};
+
+/// Formats extraction results can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  /// `<stem>.csv` with the fields plus `<stem>_<table name>.csv` per table.
+  Csv,
+  /// `<stem>.json` with the full extraction results plus `<stem>_validated.json` with the validated results.
+  Json,
+  /// One normalized record per document appended to `results.ndjson` in the output folder.
+  Ndjson,
+  /// One `results.xlsx` workbook for the whole batch in the output folder.
+  Xlsx,
+}
+
+impl FromStr for OutputFormat {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.to_lowercase().as_str() {
+      "csv" => Ok(OutputFormat::Csv),
+      "json" => Ok(OutputFormat::Json),
+      "ndjson" => Ok(OutputFormat::Ndjson),
+      "xlsx" => Ok(OutputFormat::Xlsx),
+      _ => Err(format!(
+        "unknown output format '{}', expected csv, json, ndjson or xlsx",
+        value
+      )),
+    }
+  }
+}
 
 pub struct CSVWriter;
 
 impl CSVWriter {
+  /// Writes the fields to `<stem>.csv` and every table to `<stem>_<table name>.csv`. The values of multi-valued fields
+  /// and cells are joined with `multi_value_separator`.
   pub fn write_extraction_results_to_csv(
     extraction_results: &ExtractionResults,
     document_path: &PathBuf,
     output_directory: &PathBuf,
-  ) -> Result<(), Box<dyn std::error::Error>> {
+    multi_value_separator: &str,
+  ) -> Result<(), DuError> {
     let fields_to_extract = ["FieldName", "Value", "OcrConfidence", "Confidence", "IsMissing"];
 
     let file_name = Path::new(&document_path)
       .file_stem()
-      .unwrap()
+      .unwrap_or_default()
       .to_string_lossy()
       .to_string();
     let output_dir_path = Path::new(output_directory);
-    fs::create_dir_all(&output_dir_path)?;
+    fs::create_dir_all(output_dir_path)?;
 
-    let output_file = output_dir_path.join(file_name + ".csv");
+    let output_file = output_dir_path.join(file_name.clone() + ".csv");
 
     let mut writer = Writer::from_path(output_file)?;
 
-    writer.write_record(&fields_to_extract)?;
+    writer.write_record(fields_to_extract)?;
 
     if let Some(fields) = &extraction_results.results_document.fields {
       for field in fields {
-        let field_name = field.field_name.clone();
-        let value = field.values[0].value.clone();
-        let confidence = field.values[0].confidence.clone();
-        let ocr_confidence = field.values[0].ocr_confidence.clone().to_string();
-        let is_missing = field.is_missing.clone();
-
         writer.write_record(&[
-          field_name,
-          value,
-          ocr_confidence,
-          confidence.to_string(),
-          is_missing.to_string(),
+          field.field_name.clone(),
+          join_values(field, |value| value.value.clone(), multi_value_separator),
+          join_values(field, |value| value.ocr_confidence.to_string(), multi_value_separator),
+          join_values(field, |value| value.confidence.to_string(), multi_value_separator),
+          field.is_missing.to_string(),
         ])?;
       }
     } else {
       println!("No fields found in extraction results.");
     }
 
+    writer.flush()?;
+
+    for table in extraction_results.results_document.tables.iter().flatten() {
+      let table_file = output_dir_path.join(format!("{}_{}.csv", file_name, file_name_part(&table.field_name)));
+      Self::write_table_to_csv(table, &table_file, multi_value_separator)?;
+    }
+    Ok(())
+  }
+
+  // Writes a table with one column per entry of `column_info` and one row per row index
+  fn write_table_to_csv(table: &Table, output_file: &Path, multi_value_separator: &str) -> Result<(), DuError> {
+    let mut writer = Writer::from_path(output_file)?;
+
+    let (columns, rows) = table_rows(table);
+    writer.write_record(columns.iter().map(|column| &column.field_name))?;
+    for row in rows {
+      writer.write_record(row.iter().map(|values| values.join(multi_value_separator)))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+  }
+
+  /// Writes the fields of validated results to `<stem>.csv`, joined by field ID with the extraction they were
+  /// validated from, and every table to `<stem>_<table name>.csv` with one row per cell. Each row holds the extracted
+  /// value, the validated (actual) value, whether an operator confirmed it and whether the extracted value was
+  /// correct.
+  pub fn write_validated_results_to_csv(
+    validated_results: &ValidatedResults,
+    extraction_results: &ExtractionResults,
+    document_path: &PathBuf,
+    output_directory: &PathBuf,
+    multi_value_separator: &str,
+  ) -> Result<(), DuError> {
+    let file_name = Path::new(&document_path)
+      .file_stem()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    let output_dir_path = Path::new(output_directory);
+    fs::create_dir_all(output_dir_path)?;
+
+    let output_file = output_dir_path.join(file_name.clone() + ".csv");
+
+    let mut writer = Writer::from_path(output_file)?;
+
+    let fields_to_extract = [
+      "FieldName",
+      "Value",
+      "OcrConfidence",
+      "Confidence",
+      "IsMissing",
+      "ActualValue",
+      "OperatorConfirmed",
+      "IsCorrect",
+    ];
+
+    writer.write_record(fields_to_extract)?;
+
+    let validated_document = &validated_results.result.validated_extraction_results.results_document;
+    let extracted_fields: HashMap<&str, &Field> = extraction_results
+      .results_document
+      .fields
+      .iter()
+      .flatten()
+      .map(|field| (field.field_id.as_str(), field))
+      .collect();
+    let validated_fields = validated_document.fields.as_deref().unwrap_or_default();
+
+    for validated_field in validated_fields {
+      let extraction_field = extracted_fields.get(validated_field.field_id.as_str());
+      let join_extracted = |value: fn(&FieldValue) -> String| {
+        extraction_field
+          .map(|field| join_values(field, value, multi_value_separator))
+          .unwrap_or_default()
+      };
+      let extracted_value = join_extracted(|value| value.value.clone());
+      let validated_value = join_values(validated_field, |value| value.value.clone(), multi_value_separator);
+      let is_correct = extracted_value == validated_value;
+
+      writer.write_record(&[
+        validated_field.field_name.clone(),
+        extracted_value,
+        join_extracted(|value| value.ocr_confidence.to_string()),
+        join_extracted(|value| value.confidence.to_string()),
+        extraction_field
+          .map_or(validated_field.is_missing, |field| field.is_missing)
+          .to_string(),
+        validated_value,
+        validated_field.operator_confirmed.to_string(),
+        is_correct.to_string(),
+      ])?;
+    }
+
+    // Fields the validated results no longer contain were removed by the operator
+    for extraction_field in extraction_results.results_document.fields.iter().flatten() {
+      if validated_fields
+        .iter()
+        .all(|validated_field| validated_field.field_id != extraction_field.field_id)
+      {
+        let extracted_value = join_values(extraction_field, |value| value.value.clone(), multi_value_separator);
+        writer.write_record(&[
+          extraction_field.field_name.clone(),
+          extracted_value.clone(),
+          join_values(
+            extraction_field,
+            |value| value.ocr_confidence.to_string(),
+            multi_value_separator,
+          ),
+          join_values(
+            extraction_field,
+            |value| value.confidence.to_string(),
+            multi_value_separator,
+          ),
+          extraction_field.is_missing.to_string(),
+          String::new(),
+          false.to_string(),
+          extracted_value.is_empty().to_string(),
+        ])?;
+      }
+    }
+
+    writer.flush()?;
+
+    let extracted_tables: HashMap<&str, &Table> = extraction_results
+      .results_document
+      .tables
+      .iter()
+      .flatten()
+      .map(|table| (table.field_id.as_str(), table))
+      .collect();
+    for validated_table in validated_document.tables.iter().flatten() {
+      let table_file = output_dir_path.join(format!(
+        "{}_{}.csv",
+        file_name,
+        file_name_part(&validated_table.field_name)
+      ));
+      Self::write_validated_table_to_csv(
+        validated_table,
+        extracted_tables.get(validated_table.field_id.as_str()).copied(),
+        &table_file,
+        multi_value_separator,
+      )?;
+    }
+    Ok(())
+  }
+
+  // Writes one row per table cell with its extracted and validated value; cells are matched by row index and column
+  // field ID, so rows and columns the operator added or removed show up with an empty value on the other side
+  fn write_validated_table_to_csv(
+    validated_table: &Table,
+    extracted_table: Option<&Table>,
+    output_file: &Path,
+    multi_value_separator: &str,
+  ) -> Result<(), DuError> {
+    let mut writer = Writer::from_path(output_file)?;
+    writer.write_record([
+      "Row",
+      "Column",
+      "Value",
+      "ActualValue",
+      "OperatorConfirmed",
+      "IsCorrect",
+    ])?;
+
+    // Column field IDs and names in validated order, followed by columns only the extraction has
+    let mut columns: Vec<(&str, &str)> = Vec::new();
+    let validated_columns = validated_table.values.iter().flat_map(|value| &value.column_info);
+    let extracted_columns = extracted_table
+      .into_iter()
+      .flat_map(|table| &table.values)
+      .flat_map(|value| &value.column_info);
+    for (field_id, field_name) in validated_columns
+      .map(|column| (column.field_id.as_str(), column.field_name.as_str()))
+      .chain(extracted_columns.map(|column| (column.field_id.as_str(), column.field_name.as_str())))
+    {
+      if columns.iter().all(|(id, _)| *id != field_id) {
+        columns.push((field_id, field_name));
+      }
+    }
+
+    let mut extracted_cells: BTreeMap<(i32, &str), String> = BTreeMap::new();
+    for table_value in extracted_table.into_iter().flat_map(|table| &table.values) {
+      for cell in table_value.cells.iter().filter(|cell| !cell.is_header) {
+        if let Some(column) = usize::try_from(cell.column_index)
+          .ok()
+          .and_then(|column_index| table_value.column_info.get(column_index))
+        {
+          let value = cell.values.as_deref().unwrap_or_default().join(multi_value_separator);
+          extracted_cells.insert((cell.row_index, column.field_id.as_str()), value);
+        }
+      }
+    }
+    let mut validated_cells: BTreeMap<(i32, &str), (String, bool)> = BTreeMap::new();
+    for table_value in &validated_table.values {
+      for cell in table_value.cells.iter().filter(|cell| !cell.is_header) {
+        if let Some(column) = usize::try_from(cell.column_index)
+          .ok()
+          .and_then(|column_index| table_value.column_info.get(column_index))
+        {
+          let value = cell.values.as_deref().unwrap_or_default().join(multi_value_separator);
+          validated_cells.insert(
+            (cell.row_index, column.field_id.as_str()),
+            (value, cell.operator_confirmed),
+          );
+        }
+      }
+    }
+
+    let mut rows: Vec<i32> = extracted_cells
+      .keys()
+      .chain(validated_cells.keys())
+      .map(|(row_index, _)| *row_index)
+      .collect();
+    rows.sort_unstable();
+    rows.dedup();
+    for row_index in rows {
+      for (field_id, field_name) in &columns {
+        let key = (row_index, *field_id);
+        let extracted_value = extracted_cells.get(&key);
+        let validated_value = validated_cells.get(&key);
+        if extracted_value.is_none() && validated_value.is_none() {
+          continue;
+        }
+        let extracted_value = extracted_value.cloned().unwrap_or_default();
+        let (validated_value, operator_confirmed) = validated_value.cloned().unwrap_or_default();
+        let is_correct = extracted_value == validated_value;
+        writer.write_record(&[
+          row_index.to_string(),
+          field_name.to_string(),
+          extracted_value,
+          validated_value,
+          operator_confirmed.to_string(),
+          is_correct.to_string(),
+        ])?;
+      }
+    }
+
     writer.flush()?;
     Ok(())
   }
 
-  // pub fn write_validated_results_to_csv(
-  //   validated_results: &ValidatedResults,
-  //   extraction_results: &ExtractionResults,
-  //   document_path: &PathBuf,
-  //   output_directory: &PathBuf,
-  // ) -> Option<Result<(), Box<dyn std::error::Error>>> {
-  //   let file_name = Path::new(&document_path)
-  //       .file_stem()
-  //       .unwrap()
-  //       .to_string_lossy()
-  //       .to_string();
-  //
-  //   let output_dir_path = { Path::new(output_directory) };
-  //
-  //   let output_file = output_dir_path.join(file_name + ".csv");
-  //
-  //   let mut writer = Writer::from_path(output_file)?;
-  //
-  //   let fields_to_extract = [
-  //     "FieldName",
-  //     "Value",
-  //     "OcrConfidence",
-  //     "Confidence",
-  //     "IsMissing",
-  //     "ActualValue",
-  //     "OperatorConfirmed",
-  //     "IsCorrect",
-  //   ];
-  //
-  //   writer.write_record(&fields_to_extract)?;
-  //
-  //   if let Some(validated_fields) = &validated_results
-  //       .result
-  //       .validated_extraction_results
-  //       .results_document
-  //       .fields
-  //   {
-  //     for validated_field in validated_fields {
-  //       let field_name = &validated_field.field_name;
-  //
-  //       if let Some(extraction_field) = extraction_results
-  //           .results_document
-  //           .fields
-  //           .as_ref()
-  //           .and_then(|fields| fields.iter().find(|&field| field.field_name == *field_name))
-  //       {
-  //         let extracted_value = extraction_field
-  //             .values
-  //             .get(0)
-  //             .and_then(|value| value.value.and_then(|v| v.as_str()))
-  //             .unwrap_or_default();
-  //         let confidence = extraction_field
-  //             .values
-  //             .get(0)
-  //             .and_then(|value| value.confidence.and_then(|v| v.as_str()))
-  //             .unwrap_or_default();
-  //         let ocr_confidence = extraction_field
-  //             .values
-  //             .get(0)
-  //             .and_then(|value| value.ocr_confidence.and_then(|v| v.as_str()))
-  //             .unwrap_or_default();
-  //         let is_missing = extraction_field
-  //             .is_missing
-  //             .then(|v| v.as_bool())
-  //             .unwrap_or_default();
-  //
-  //         let validated_value = validated_field
-  //             .values
-  //             .get(0)
-  //             .and_then(|value| value.value.and_then(|v| v.as_str()))
-  //             .unwrap_or_default();
-  //         let operator_confirmed = validated_field
-  //             .operator_confirmed
-  //             .and_then(|v| v.as_bool())
-  //             .unwrap_or_default();
-  //         let is_correct =
-  //             validated_value.is_empty() && extracted_value.is_empty() || validated_value == extracted_value;
-  //
-  //         writer.write_record(&[
-  //           field_name,
-  //           extracted_value,
-  //           ocr_confidence,
-  //           confidence,
-  //           &is_missing.to_string(),
-  //           validated_value,
-  //           &operator_confirmed.to_string(),
-  //           &is_correct.to_string(),
-  //         ])?;
-  //       }
-  //     }
-  //   }
-  // }
-
-  pub fn print_csv_results(document_path: &PathBuf, output_directory: &PathBuf) -> Result<(), io::Error> {
+  pub fn print_csv_results(document_path: &PathBuf, output_directory: &PathBuf) -> Result<(), DuError> {
     // Extract file name without extension
     let file_name = std::path::Path::new(document_path)
       .file_stem()
@@ -182,6 +366,9 @@ impl CSVWriter {
       }
     }
 
+    // Build the whole table first and print it in one go so concurrent documents don't interleave their rows
+    let mut table = format!("[{}] Results\n", document_path.display());
+
     // Print headers
     let header_format = headers
       .iter()
@@ -189,8 +376,7 @@ impl CSVWriter {
       .map(|(header, &width)| format!("{:<width$}", header, width = width))
       .collect::<Vec<_>>()
       .join("|");
-    println!("{}", header_format);
-    println!("{}", "-".repeat(header_format.len()));
+    table.push_str(&format!("{}\n{}\n", header_format, "-".repeat(header_format.len())));
 
     // Reset reader to the beginning of the file
     let pos = Position::new();
@@ -205,9 +391,855 @@ impl CSVWriter {
         .map(|(field, &width)| format!("{:<width$}", field, width = width))
         .collect::<Vec<_>>()
         .join("|");
-      println!("{}", row_format);
+      table.push_str(&format!("{}\n", row_format));
+    }
+    print!("{}", table);
+
+    Ok(())
+  }
+}
+
+pub struct JsonWriter;
+
+impl JsonWriter {
+  /// Writes the full extraction results to `<stem>.json` and, if given, the validated results to
+  /// `<stem>_validated.json`.
+  pub fn write_results(
+    extraction_results: &ExtractionResults,
+    validated_results: Option<&ValidatedResults>,
+    document_path: &Path,
+    output_directory: &Path,
+  ) -> Result<(), DuError> {
+    let file_name = document_path
+      .file_stem()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    fs::create_dir_all(output_directory)?;
+
+    let extraction_file = File::create(output_directory.join(format!("{}.json", file_name)))?;
+    serde_json::to_writer_pretty(extraction_file, extraction_results).map_err(io::Error::from)?;
+    if let Some(validated_results) = validated_results {
+      let validated_file = File::create(output_directory.join(format!("{}_validated.json", file_name)))?;
+      serde_json::to_writer_pretty(validated_file, validated_results).map_err(io::Error::from)?;
+    }
+    Ok(())
+  }
+}
+
+// One line of `results.ndjson`: the final values of a document, validated where validation took place
+#[derive(Serialize)]
+struct NdjsonRecord<'a> {
+  document: String,
+  // Pages of the document holding these results, e.g. `1-2`, when the file holds several documents
+  pages: Option<&'a str>,
+  document_id: &'a str,
+  document_type_id: &'a str,
+  extractor_id: &'a str,
+  validated: bool,
+  fields: Vec<NdjsonField<'a>>,
+  tables: Vec<NdjsonTable<'a>>,
+}
+
+#[derive(Serialize)]
+struct NdjsonField<'a> {
+  field_id: &'a str,
+  field_name: &'a str,
+  field_type: &'a str,
+  is_missing: bool,
+  operator_confirmed: bool,
+  values: Vec<NdjsonValue<'a>>,
+}
+
+#[derive(Serialize)]
+struct NdjsonValue<'a> {
+  value: &'a str,
+  confidence: f64,
+  ocr_confidence: f64,
+}
+
+#[derive(Serialize)]
+struct NdjsonTable<'a> {
+  field_id: &'a str,
+  field_name: &'a str,
+  /// Cell values by column name; a cell can hold several values.
+  rows: Vec<BTreeMap<&'a str, &'a [String]>>,
+}
+
+/// Appends one normalized JSON record per document to a single file shared by every document of a run.
+pub struct NdjsonWriter {
+  file: Mutex<File>,
+}
+
+impl NdjsonWriter {
+  /// Opens `path` for appending, creating it if needed.
+  pub fn open(path: &Path) -> Result<NdjsonWriter, DuError> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(NdjsonWriter { file: Mutex::new(file) })
+  }
+
+  /// Appends the record of one document, taking the values from `validated_results` when given. `pages` is the page
+  /// range of the document within the file at `document_path`, when that file holds several documents.
+  pub fn append(
+    &self,
+    document_path: &Path,
+    pages: Option<&str>,
+    extractor_id: &str,
+    extraction_results: &ExtractionResults,
+    validated_results: Option<&ValidatedResults>,
+  ) -> Result<(), DuError> {
+    let results_document = final_results_document(extraction_results, validated_results);
+    let record = NdjsonRecord {
+      document: document_path.display().to_string(),
+      pages,
+      document_id: &extraction_results.document_id,
+      document_type_id: &results_document.document_type_id,
+      extractor_id,
+      validated: validated_results.is_some(),
+      fields: results_document
+        .fields
+        .iter()
+        .flatten()
+        .map(|field| NdjsonField {
+          field_id: &field.field_id,
+          field_name: &field.field_name,
+          field_type: &field.field_type,
+          is_missing: field.is_missing,
+          operator_confirmed: field.operator_confirmed,
+          values: field
+            .values
+            .iter()
+            .map(|value| NdjsonValue {
+              value: &value.value,
+              confidence: value.confidence,
+              ocr_confidence: value.ocr_confidence,
+            })
+            .collect(),
+        })
+        .collect(),
+      tables: results_document
+        .tables
+        .iter()
+        .flatten()
+        .map(|table| {
+          let (columns, rows) = table_rows(table);
+          NdjsonTable {
+            field_id: &table.field_id,
+            field_name: &table.field_name,
+            rows: rows
+              .into_iter()
+              .map(|row| {
+                columns
+                  .iter()
+                  .map(|column| column.field_name.as_str())
+                  .zip(row)
+                  .collect()
+              })
+              .collect(),
+          }
+        })
+        .collect(),
+    };
+
+    let mut line = serde_json::to_vec(&record).map_err(io::Error::from)?;
+    line.push(b'\n');
+    // One write per record under the lock keeps lines of concurrent documents from interleaving
+    let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    file.write_all(&line)?;
+    Ok(())
+  }
+}
+
+// Number of leading fields of each document type shown on the summary sheet
+const SUMMARY_FIELD_COUNT: usize = 5;
+
+// Results of one document type of a document, kept until the workbook is written
+struct WorkbookDocument {
+  document: String,
+  pages: Option<String>,
+  document_type_id: String,
+  validated: bool,
+  fields: Vec<WorkbookField>,
+  tables: Vec<WorkbookTable>,
+}
+
+struct WorkbookField {
+  name: String,
+  value: String,
+  // Lowest confidence of the field's values; missing fields have none
+  confidence: Option<f64>,
+  // A value's confidence or OCR confidence is below the field's threshold
+  low_confidence: bool,
+}
+
+struct WorkbookTable {
+  field_id: String,
+  name: String,
+  columns: Vec<String>,
+  rows: Vec<WorkbookRow>,
+}
+
+// Cell values of a table row together with the confidence of the table value holding the row
+struct WorkbookRow {
+  confidence: f64,
+  low_confidence: bool,
+  values: Vec<String>,
+}
+
+/// Collects the results of a batch and writes them to one Excel workbook: a summary sheet with one row per document,
+/// one sheet of field values per document type and one sheet of rows per table type, e.g. invoice line items.
+///
+/// Fields and table rows with a confidence or OCR confidence below the threshold `thresholds` give them, by document
+/// type and field, are highlighted.
+pub struct XlsxWriter {
+  thresholds: Thresholds,
+  multi_value_separator: String,
+  documents: Mutex<Vec<WorkbookDocument>>,
+}
+
+impl XlsxWriter {
+  pub fn new(thresholds: Thresholds, multi_value_separator: &str) -> XlsxWriter {
+    XlsxWriter {
+      thresholds,
+      multi_value_separator: multi_value_separator.to_string(),
+      documents: Mutex::new(Vec::new()),
     }
+  }
+
+  /// Adds the results of one document type of a document, taking the values from `validated_results` when given.
+  /// `pages` is the page range of the document within the file at `document_path`, when that file holds several.
+  pub fn add(
+    &self,
+    document_path: &Path,
+    pages: Option<&str>,
+    extraction_results: &ExtractionResults,
+    validated_results: Option<&ValidatedResults>,
+  ) {
+    let results_document = final_results_document(extraction_results, validated_results);
+    let document_type_id = &results_document.document_type_id;
+    let separator = &self.multi_value_separator;
+    let document = WorkbookDocument {
+      document: document_path.display().to_string(),
+      pages: pages.map(str::to_string),
+      document_type_id: results_document.document_type_id.clone(),
+      validated: validated_results.is_some(),
+      fields: results_document
+        .fields
+        .iter()
+        .flatten()
+        .map(|field| WorkbookField {
+          name: field.field_name.clone(),
+          value: field
+            .values
+            .iter()
+            .map(|value| value.value.as_str())
+            .collect::<Vec<_>>()
+            .join(separator),
+          confidence: field.values.iter().map(|value| value.confidence).reduce(f64::min),
+          low_confidence: self.thresholds.low_confidence_field(document_type_id, field).is_some(),
+        })
+        .collect(),
+      tables: results_document
+        .tables
+        .iter()
+        .flatten()
+        .map(|table| {
+          let columns = table.values.first().map_or(&[][..], |value| &value.column_info[..]);
+          WorkbookTable {
+            field_id: table.field_id.clone(),
+            name: table.field_name.clone(),
+            columns: columns.iter().map(|column| column.field_name.clone()).collect(),
+            rows: table
+              .values
+              .iter()
+              .flat_map(|table_value| {
+                let low_confidence = self
+                  .thresholds
+                  .is_low_confidence_row(document_type_id, table, table_value);
+                table_value_rows(table_value, columns.len())
+                  .into_iter()
+                  .map(move |row| WorkbookRow {
+                    confidence: table_value.confidence,
+                    low_confidence,
+                    values: row.iter().map(|values| values.join(separator)).collect(),
+                  })
+              })
+              .collect(),
+          }
+        })
+        .collect(),
+    };
+    self.lock().push(document);
+  }
+
+  /// Whether no results have been added yet.
+  pub fn is_empty(&self) -> bool {
+    self.lock().is_empty()
+  }
+
+  /// Writes everything added so far to the workbook at `path`, replacing it.
+  pub fn save(&self, path: &Path) -> Result<(), DuError> {
+    let documents = self.lock();
+    let mut documents: Vec<&WorkbookDocument> = documents.iter().collect();
+    documents
+      .sort_by(|a, b| (&a.document, &a.pages, &a.document_type_id).cmp(&(&b.document, &b.pages, &b.document_type_id)));
+
+    let mut workbook = Workbook::new();
+    let mut sheet_names = HashSet::new();
+    self.write_summary_sheet(&mut workbook, &mut sheet_names, &documents)?;
+
+    let mut document_types: BTreeMap<&str, Vec<&WorkbookDocument>> = BTreeMap::new();
+    let mut table_types: BTreeMap<(&str, &str), Vec<(&WorkbookDocument, &WorkbookTable)>> = BTreeMap::new();
+    for document in &documents {
+      document_types
+        .entry(&document.document_type_id)
+        .or_default()
+        .push(document);
+      for table in &document.tables {
+        table_types
+          .entry((&document.document_type_id, &table.field_id))
+          .or_default()
+          .push((document, table));
+      }
+    }
+    for (document_type_id, documents) in document_types {
+      let name = sheet_name(document_type_id, &mut sheet_names);
+      self.write_fields_sheet(workbook.add_worksheet().set_name(name)?, &documents)?;
+    }
+    for ((document_type_id, _), tables) in table_types {
+      let name = sheet_name(
+        &format!("{} - {}", document_type_id, tables[0].1.name),
+        &mut sheet_names,
+      );
+      self.write_table_sheet(workbook.add_worksheet().set_name(name)?, &tables)?;
+    }
+
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    workbook.save(path)?;
+    Ok(())
+  }
+
+  // One row per document with its lowest confidence, the fields below the threshold and the first fields of its type
+  fn write_summary_sheet(
+    &self,
+    workbook: &mut Workbook,
+    sheet_names: &mut HashSet<String>,
+    documents: &[&WorkbookDocument],
+  ) -> Result<(), DuError> {
+    let mut summary_fields: Vec<&str> = Vec::new();
+    for document in documents {
+      for field in document.fields.iter().take(SUMMARY_FIELD_COUNT) {
+        if !summary_fields.contains(&field.name.as_str()) {
+          summary_fields.push(&field.name);
+        }
+      }
+    }
+
+    let worksheet = workbook.add_worksheet().set_name(sheet_name("Summary", sheet_names))?;
+    let mut headers = vec![
+      "Document",
+      "Pages",
+      "Document Type",
+      "Validated",
+      "Lowest Confidence",
+      "Low Confidence Fields",
+    ];
+    headers.extend(&summary_fields);
+    write_headers(worksheet, &headers)?;
+
+    for (index, document) in documents.iter().enumerate() {
+      let row = index as RowNum + 1;
+      worksheet.write_string(row, 0, &document.document)?;
+      if let Some(pages) = &document.pages {
+        worksheet.write_string(row, 1, pages)?;
+      }
+      worksheet.write_string(row, 2, &document.document_type_id)?;
+      worksheet.write_boolean(row, 3, document.validated)?;
+      let lowest_confidence = document
+        .fields
+        .iter()
+        .filter_map(|field| field.confidence)
+        .reduce(f64::min);
+      let low_confidence_fields: Vec<&str> = document
+        .fields
+        .iter()
+        .filter(|field| field.low_confidence)
+        .map(|field| field.name.as_str())
+        .collect();
+      if let Some(lowest_confidence) = lowest_confidence {
+        let format = highlight_format(!low_confidence_fields.is_empty());
+        worksheet.write_number_with_format(row, 4, lowest_confidence, &format)?;
+      }
+      if self.thresholds.routes_extraction() {
+        worksheet.write_string(row, 5, low_confidence_fields.join(", "))?;
+      }
+      for (column, name) in summary_fields.iter().enumerate() {
+        if let Some(field) = document.fields.iter().find(|field| field.name == *name) {
+          worksheet.write_string(
+            row,
+            (headers.len() - summary_fields.len() + column) as ColNum,
+            &field.value,
+          )?;
+        }
+      }
+    }
+    worksheet.autofit();
+    Ok(())
+  }
+
+  // One row per document of a type with a value and a confidence column per field
+  fn write_fields_sheet(&self, worksheet: &mut Worksheet, documents: &[&WorkbookDocument]) -> Result<(), DuError> {
+    let mut field_names: Vec<&str> = Vec::new();
+    for document in documents {
+      for field in &document.fields {
+        if !field_names.contains(&field.name.as_str()) {
+          field_names.push(&field.name);
+        }
+      }
+    }
+
+    let confidence_headers: Vec<String> = field_names.iter().map(|name| format!("{} Confidence", name)).collect();
+    let mut headers = vec!["Document", "Pages", "Validated"];
+    for (name, confidence_header) in field_names.iter().zip(&confidence_headers) {
+      headers.push(name);
+      headers.push(confidence_header);
+    }
+    write_headers(worksheet, &headers)?;
+
+    for (index, document) in documents.iter().enumerate() {
+      let row = index as RowNum + 1;
+      worksheet.write_string(row, 0, &document.document)?;
+      if let Some(pages) = &document.pages {
+        worksheet.write_string(row, 1, pages)?;
+      }
+      worksheet.write_boolean(row, 2, document.validated)?;
+      for field in &document.fields {
+        let Some(position) = field_names.iter().position(|name| *name == field.name) else {
+          continue;
+        };
+        let column = (3 + 2 * position) as ColNum;
+        let format = highlight_format(field.low_confidence);
+        worksheet.write_string_with_format(row, column, &field.value, &format)?;
+        if let Some(confidence) = field.confidence {
+          worksheet.write_number_with_format(row, column + 1, confidence, &format)?;
+        }
+      }
+    }
+    worksheet.autofit();
+    Ok(())
+  }
+
+  // One row per table row of a table type, with the confidence of the table the row belongs to
+  fn write_table_sheet(
+    &self,
+    worksheet: &mut Worksheet,
+    tables: &[(&WorkbookDocument, &WorkbookTable)],
+  ) -> Result<(), DuError> {
+    let mut columns: Vec<&str> = Vec::new();
+    for (_, table) in tables {
+      for column in &table.columns {
+        if !columns.contains(&column.as_str()) {
+          columns.push(column);
+        }
+      }
+    }
+
+    let mut headers = vec!["Document", "Pages", "Row", "Confidence"];
+    headers.extend(&columns);
+    write_headers(worksheet, &headers)?;
+
+    let mut row: RowNum = 0;
+    for (document, table) in tables {
+      for (row_number, table_row) in table.rows.iter().enumerate() {
+        row += 1;
+        worksheet.write_string(row, 0, &document.document)?;
+        if let Some(pages) = &document.pages {
+          worksheet.write_string(row, 1, pages)?;
+        }
+        worksheet.write_number(row, 2, (row_number + 1) as f64)?;
+        // A low confidence row is highlighted from its confidence to its last column, cells without a value included
+        let format = highlight_format(table_row.low_confidence);
+        worksheet.write_number_with_format(row, 3, table_row.confidence, &format)?;
+        if table_row.low_confidence {
+          for column in 4..headers.len() {
+            worksheet.write_blank(row, column as ColNum, &format)?;
+          }
+        }
+        for (column, value) in table.columns.iter().zip(&table_row.values) {
+          if let Some(position) = columns.iter().position(|name| name == column) {
+            worksheet.write_string_with_format(row, (4 + position) as ColNum, value, &format)?;
+          }
+        }
+      }
+    }
+    worksheet.autofit();
+    Ok(())
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, Vec<WorkbookDocument>> {
+    self.documents.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+}
+
+// Format of a cell: red for values below their confidence threshold, the default otherwise
+fn highlight_format(low_confidence: bool) -> Format {
+  if low_confidence {
+    Format::new()
+      .set_background_color(Color::RGB(0xFFC7CE))
+      .set_font_color(Color::RGB(0x9C0006))
+  } else {
+    Format::new()
+  }
+}
+
+// Writes bold column headers to the first row and keeps them visible while scrolling
+fn write_headers(worksheet: &mut Worksheet, headers: &[&str]) -> Result<(), DuError> {
+  let bold = Format::new().set_bold();
+  for (column, header) in headers.iter().enumerate() {
+    worksheet.write_string_with_format(0, column as ColNum, *header, &bold)?;
+  }
+  worksheet.set_freeze_panes(1, 0)?;
+  Ok(())
+}
 
+// Turns `name` into a sheet name Excel accepts that is not taken yet: at most 31 characters, none of []:*?/\ and
+// unique ignoring case
+fn sheet_name(name: &str, used: &mut HashSet<String>) -> String {
+  let cleaned: String = name
+    .chars()
+    .map(|c| if "[]:*?/\\".contains(c) { '_' } else { c })
+    .collect();
+  let base = match cleaned.trim().trim_matches('\'') {
+    "" => "Sheet",
+    base => base,
+  };
+  let mut counter = 1;
+  loop {
+    let suffix = if counter == 1 {
+      String::new()
+    } else {
+      format!(" ({})", counter)
+    };
+    let candidate: String = base.chars().take(31 - suffix.len()).collect::<String>() + &suffix;
+    if used.insert(candidate.to_lowercase()) {
+      return candidate;
+    }
+    counter += 1;
+  }
+}
+
+pub struct OcrWriter;
+
+impl OcrWriter {
+  /// Writes the digitization DOM to `<stem>_dom.json` and the document text to `<stem>.txt`, skipping whichever the
+  /// API did not return.
+  pub fn write_digitization_results(
+    digitization_output: &DigitizationOutput,
+    document_path: &PathBuf,
+    output_directory: &PathBuf,
+  ) -> Result<(), DuError> {
+    let file_name = Path::new(document_path)
+      .file_stem()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    fs::create_dir_all(output_directory)?;
+
+    if let Some(document_object_model) = &digitization_output.document_object_model {
+      let dom_file = File::create(output_directory.join(format!("{}_dom.json", file_name)))?;
+      serde_json::to_writer_pretty(dom_file, document_object_model).map_err(io::Error::from)?;
+    }
+    if let Some(document_text) = &digitization_output.document_text {
+      fs::write(output_directory.join(format!("{}.txt", file_name)), document_text)?;
+    }
     Ok(())
   }
 }
+
+// Columns of a table and the cell values of each row, ordered by row index. Header cells are skipped since the column
+// names come from `column_info`.
+fn table_rows(table: &Table) -> (&[ColumnInfo], Vec<Vec<&[String]>>) {
+  let columns = table.values.first().map_or(&[][..], |value| &value.column_info[..]);
+  let rows = table
+    .values
+    .iter()
+    .flat_map(|table_value| table_value_rows(table_value, columns.len()))
+    .collect();
+  (columns, rows)
+}
+
+// Cell values of each row of one table value, ordered by row index
+fn table_value_rows(table_value: &TableValue, column_count: usize) -> Vec<Vec<&[String]>> {
+  let mut rows: BTreeMap<i32, Vec<&[String]>> = BTreeMap::new();
+  for cell in table_value.cells.iter().filter(|cell| !cell.is_header) {
+    let row = rows
+      .entry(cell.row_index)
+      .or_insert_with(|| vec![&[][..]; column_count]);
+    if let Some(values) = usize::try_from(cell.column_index)
+      .ok()
+      .and_then(|column_index| row.get_mut(column_index))
+    {
+      *values = cell.values.as_deref().unwrap_or_default();
+    }
+  }
+  rows.into_values().collect()
+}
+
+// Joins one property of every value of a field; missing fields have no values, multi-valued fields have several
+fn join_values(field: &Field, value: impl Fn(&FieldValue) -> String, separator: &str) -> String {
+  field.values.iter().map(value).collect::<Vec<_>>().join(separator)
+}
+
+// The values a document ends up with: the validated ones if it was validated, else the extracted ones
+fn final_results_document<'a>(
+  extraction_results: &'a ExtractionResults,
+  validated_results: Option<&'a ValidatedResults>,
+) -> &'a ResultsDocument {
+  match validated_results {
+    Some(validated_results) => &validated_results.result.validated_extraction_results.results_document,
+    None => &extraction_results.results_document,
+  }
+}
+
+// Replaces characters that are not allowed or awkward in file names, e.g. in a table name like "Items / Services"
+fn file_name_part(name: &str) -> String {
+  name
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || c == '-' || c == '_' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value};
+
+  use super::*;
+
+  fn value(value: &str, confidence: f64) -> Value {
+    json!({
+      "components": [], "value": value, "unformatted_value": value, "derived_fields": [],
+      "reference": { "text_start_index": 0, "text_length": 1, "tokens": [] },
+      "confidence": confidence, "operator_confirmed": false, "ocr_confidence": 1.0, "text_type": "Text"
+    })
+  }
+
+  fn field(field_id: &str, field_name: &str, values: Vec<Value>) -> Value {
+    json!({
+      "field_id": field_id, "field_name": field_name, "field_type": "Text", "is_missing": values.is_empty(),
+      "data_source": "Automatic", "values": values, "data_version": 0, "operator_confirmed": true
+    })
+  }
+
+  fn cell(row_index: i32, column_index: i32, value: &str) -> Value {
+    json!({
+      "row_index": row_index, "column_index": column_index, "is_header": false, "is_missing": false,
+      "operator_confirmed": true, "data_source": "Automatic", "data_version": 0, "values": [value]
+    })
+  }
+
+  // An "Items" table whose columns are given as (field ID, field name)
+  fn table(columns: &[(&str, &str)], cells: Vec<Value>) -> Value {
+    let column_info: Vec<Value> = columns
+      .iter()
+      .map(|(field_id, field_name)| json!({ "field_id": field_id, "field_name": field_name, "field_type": "Text" }))
+      .collect();
+    json!({
+      "field_id": "items", "field_name": "Items", "is_missing": false, "data_source": "Automatic",
+      "data_version": 0, "operator_confirmed": false,
+      "values": [{
+        "operator_confirmed": false, "confidence": 1.0, "ocr_confidence": 1.0, "cells": cells,
+        "column_info": column_info, "number_of_rows": 1
+      }]
+    })
+  }
+
+  fn results_document(fields: Vec<Value>, tables: Vec<Value>) -> Value {
+    json!({
+      "bounds": { "start_page": 0, "page_count": 1, "text_start_index": 0, "text_length": 1 },
+      "language": "eng", "document_group": "", "document_category": "", "document_type_id": "invoices",
+      "document_type_name": "Invoices", "document_type_data_version": 0, "data_version": 0,
+      "document_type_source": "Automatic", "document_type_field": value("invoices", 1.0),
+      "fields": fields, "tables": tables
+    })
+  }
+
+  fn extraction(fields: Vec<Value>, tables: Vec<Value>) -> ExtractionResults {
+    serde_json::from_value(json!({
+      "document_id": "doc", "results_version": 0, "extractor_payloads": null, "business_rules_results": null,
+      "results_document": results_document(fields, tables)
+    }))
+    .unwrap()
+  }
+
+  fn validated(fields: Vec<Value>, tables: Vec<Value>) -> ValidatedResults {
+    let user = json!({ "id": 1, "email_address": null });
+    serde_json::from_value(json!({
+      "status": "Succeeded", "created_at": "", "last_updated_at": "",
+      "result": {
+        "action_status": "Completed",
+        "action_data": {
+          "action_data_type": "Validation", "id": 1, "status": "Completed", "title": "", "priority": "Medium",
+          "task_catalog_name": "", "task_url": "", "folder_path": "", "folder_id": 1, "action": "Completed",
+          "data": { "validated_extraction_results_path": "", "document_rejection_details": null },
+          "is_deleted": false, "assigned_to_user": null, "creator_user": user, "deleter_user": null,
+          "last_modifier_user": user, "completed_by_user": user, "creation_time": "", "last_assigned_time": "",
+          "completion_time": ""
+        },
+        "validated_extraction_results": {
+          "document_id": "doc", "results_version": 1, "extractor_payloads": null, "business_rules_results": null,
+          "results_document": results_document(fields, tables)
+        }
+      }
+    }))
+    .unwrap()
+  }
+
+  // Writes the validated CSV files of `invoice.pdf` to a fresh directory and returns it
+  fn write_validated(
+    name: &str,
+    validated_results: &ValidatedResults,
+    extraction_results: &ExtractionResults,
+  ) -> PathBuf {
+    let output_directory = std::env::temp_dir().join(format!("rustydu-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&output_directory);
+    CSVWriter::write_validated_results_to_csv(
+      validated_results,
+      extraction_results,
+      &PathBuf::from("invoice.pdf"),
+      &output_directory,
+      "|",
+    )
+    .unwrap();
+    output_directory
+  }
+
+  fn read_rows(path: &Path) -> Vec<Vec<String>> {
+    ReaderBuilder::new()
+      .from_path(path)
+      .unwrap()
+      .records()
+      .map(|record| record.unwrap().iter().map(str::to_string).collect())
+      .collect()
+  }
+
+  #[test]
+  fn values_are_joined_with_the_separator() {
+    let results = extraction(
+      vec![field("total", "Total", vec![value("1", 0.5), value("2", 0.25)])],
+      vec![],
+    );
+    let total = &results.results_document.fields.as_ref().unwrap()[0];
+    assert_eq!(join_values(total, |value| value.value.clone(), "|"), "1|2");
+    assert_eq!(
+      join_values(total, |value| value.confidence.to_string(), ", "),
+      "0.5, 0.25"
+    );
+  }
+
+  #[test]
+  fn validated_fields_are_joined_by_id() {
+    let extraction_results = extraction(
+      vec![
+        field("total", "Total", vec![value("10", 0.9)]),
+        field("date", "Date", vec![value("2024-01-01", 0.8)]),
+      ],
+      vec![],
+    );
+    // Renamed and reordered by the operator, still matched by ID
+    let validated_results = validated(
+      vec![
+        field("date", "Invoice Date", vec![value("2024-01-01", 1.0)]),
+        field("total", "Amount", vec![value("12", 1.0)]),
+      ],
+      vec![],
+    );
+    let output_directory = write_validated("validated-fields", &validated_results, &extraction_results);
+    let rows = read_rows(&output_directory.join("invoice.csv"));
+    assert_eq!(
+      rows,
+      [
+        [
+          "Invoice Date",
+          "2024-01-01",
+          "1",
+          "0.8",
+          "false",
+          "2024-01-01",
+          "true",
+          "true"
+        ],
+        ["Amount", "10", "1", "0.9", "false", "12", "true", "false"],
+      ]
+    );
+    fs::remove_dir_all(output_directory).unwrap();
+  }
+
+  #[test]
+  fn fields_removed_by_the_operator_are_kept() {
+    let extraction_results = extraction(
+      vec![
+        field("total", "Total", vec![value("10", 0.9)]),
+        field("notes", "Notes", vec![]),
+      ],
+      vec![],
+    );
+    let validated_results = validated(vec![], vec![]);
+    let output_directory = write_validated("removed-fields", &validated_results, &extraction_results);
+    let rows = read_rows(&output_directory.join("invoice.csv"));
+    assert_eq!(
+      rows,
+      [
+        ["Total", "10", "1", "0.9", "false", "", "false", "false"],
+        ["Notes", "", "", "", "true", "", "false", "true"],
+      ]
+    );
+    fs::remove_dir_all(output_directory).unwrap();
+  }
+
+  #[test]
+  fn table_cells_are_matched_by_row_and_column_id() {
+    let extraction_results = extraction(
+      vec![],
+      vec![table(
+        &[("description", "Description"), ("amount", "Amount")],
+        vec![
+          cell(0, 0, "Pens"),
+          cell(0, 1, "5"),
+          cell(1, 0, "Paper"),
+          cell(1, 1, "7"),
+        ],
+      )],
+    );
+    // Columns swapped and the second row removed by the operator
+    let validated_results = validated(
+      vec![],
+      vec![table(
+        &[("amount", "Amount"), ("description", "Description")],
+        vec![cell(0, 0, "6"), cell(0, 1, "Pens")],
+      )],
+    );
+    let output_directory = write_validated("validated-table", &validated_results, &extraction_results);
+    let rows = read_rows(&output_directory.join("invoice_Items.csv"));
+    assert_eq!(
+      rows,
+      [
+        ["0", "Amount", "5", "6", "true", "false"],
+        ["0", "Description", "Pens", "Pens", "true", "true"],
+        ["1", "Amount", "7", "", "false", "false"],
+        ["1", "Description", "Paper", "", "false", "false"],
+      ]
+    );
+    fs::remove_dir_all(output_directory).unwrap();
+  }
+}
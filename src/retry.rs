@@ -0,0 +1,194 @@
+use std::{
+  future::Future,
+  time::{Duration, SystemTime},
+};
+
+use rand::Rng;
+use reqwest::{
+  header::{HeaderMap, RETRY_AFTER},
+  Response, StatusCode,
+};
+
+use crate::error::DuError;
+
+/// Controls how failed requests to the Document Understanding API are retried.
+///
+/// A request is retried when it fails to reach the server, times out, or comes back with one of `retry_statuses`.
+/// The delay before attempt `n + 1` is `base_delay * 2^(n - 1)`, capped at `max_delay`, plus a random amount of up to
+/// `jitter`. A `Retry-After` header sent by the server takes precedence over the computed delay, but is capped at
+/// `max_delay` as well.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+  /// Total number of attempts, including the first one. `1` disables retries.
+  pub max_attempts: u32,
+  pub base_delay: Duration,
+  pub max_delay: Duration,
+  pub jitter: Duration,
+  pub retry_statuses: Vec<StatusCode>,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_attempts: 5,
+      base_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(60),
+      jitter: Duration::from_millis(500),
+      retry_statuses: vec![
+        StatusCode::REQUEST_TIMEOUT,
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::BAD_GATEWAY,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::GATEWAY_TIMEOUT,
+      ],
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Runs `send` until it returns a response that should not be retried or the attempts are used up.
+  ///
+  /// `send` is called once per attempt so every attempt can build a fresh request, e.g. with a refreshed token.
+  /// Retries are logged with `label`, such as the path of the document the request is for, as a `[label]` prefix.
+  pub async fn execute<F, Fut>(&self, label: Option<&str>, mut send: F) -> Result<Response, DuError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, DuError>>,
+  {
+    let prefix = label.map(|label| format!("[{}] ", label)).unwrap_or_default();
+    let mut attempt = 1;
+    loop {
+      let outcome = send().await;
+      if attempt >= self.max_attempts {
+        return outcome;
+      }
+
+      let delay = match &outcome {
+        Ok(response) if self.retry_statuses.contains(&response.status()) => {
+          let delay = retry_after(response.headers())
+            .map(|delay| delay.min(self.max_delay))
+            .unwrap_or_else(|| self.backoff(attempt));
+          eprintln!(
+            "{}{} returned {}, retrying in {:?} (attempt {}/{})",
+            prefix,
+            response.url(),
+            response.status(),
+            delay,
+            attempt,
+            self.max_attempts
+          );
+          delay
+        }
+        Err(err) if is_transient(err) => {
+          let delay = self.backoff(attempt);
+          eprintln!(
+            "{}Request failed ({}), retrying in {:?} (attempt {}/{})",
+            prefix, err, delay, attempt, self.max_attempts
+          );
+          delay
+        }
+        _ => return outcome,
+      };
+
+      tokio::time::sleep(delay).await;
+      attempt += 1;
+    }
+  }
+
+  // Exponential backoff for the given (1-based) attempt that just failed
+  fn backoff(&self, attempt: u32) -> Duration {
+    let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(attempt - 1));
+    let jitter = if self.jitter.is_zero() {
+      Duration::ZERO
+    } else {
+      rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    };
+    exponential.min(self.max_delay) + jitter
+  }
+}
+
+// Connection failures and timeouts may succeed on a second try; everything else is final
+fn is_transient(err: &DuError) -> bool {
+  match err {
+    DuError::Transport(err) => err.is_connect() || err.is_request(),
+    DuError::Timeout(_) => true,
+    _ => false,
+  }
+}
+
+// Parses a `Retry-After` header given either as delay-seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+  let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Some(Duration::from_secs(seconds));
+  }
+  let date = httpdate::parse_http_date(value).ok()?;
+  Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::header::HeaderValue;
+
+  use super::*;
+
+  fn policy() -> RetryPolicy {
+    RetryPolicy {
+      base_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(10),
+      jitter: Duration::ZERO,
+      ..RetryPolicy::default()
+    }
+  }
+
+  fn headers(retry_after: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+    headers
+  }
+
+  #[test]
+  fn backoff_doubles_per_attempt_up_to_max_delay() {
+    let policy = policy();
+    assert_eq!(policy.backoff(1), Duration::from_secs(1));
+    assert_eq!(policy.backoff(2), Duration::from_secs(2));
+    assert_eq!(policy.backoff(4), Duration::from_secs(8));
+    assert_eq!(policy.backoff(5), Duration::from_secs(10));
+    assert_eq!(policy.backoff(40), Duration::from_secs(10));
+  }
+
+  #[test]
+  fn backoff_adds_at_most_jitter() {
+    let policy = RetryPolicy {
+      jitter: Duration::from_millis(200),
+      ..policy()
+    };
+    for _ in 0..20 {
+      let delay = policy.backoff(2);
+      assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_millis(2200));
+    }
+  }
+
+  #[test]
+  fn retry_after_reads_seconds() {
+    assert_eq!(retry_after(&headers("7")), Some(Duration::from_secs(7)));
+    assert_eq!(retry_after(&headers(" 3 ")), Some(Duration::from_secs(3)));
+  }
+
+  #[test]
+  fn retry_after_reads_http_dates() {
+    let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+    let delay = retry_after(&headers(&later)).unwrap();
+    assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+    let earlier = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(30));
+    assert_eq!(retry_after(&headers(&earlier)), Some(Duration::ZERO));
+  }
+
+  #[test]
+  fn retry_after_ignores_missing_and_invalid_values() {
+    assert_eq!(retry_after(&HeaderMap::new()), None);
+    assert_eq!(retry_after(&headers("soon")), None);
+  }
+}
@@ -0,0 +1,313 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::extract::{ExtractionResults, Field, Table, TableValue};
+
+/// Confidence levels below which results need a closer look.
+///
+/// When extraction validation is enabled and any field or OCR threshold is set, only extractions with a value below
+/// its threshold are sent to validation; the others are accepted as extracted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+  pub classification_confidence: Option<f32>,
+  /// Applies to fields without a threshold of their own or of their document type.
+  pub field_confidence: Option<f32>,
+  /// Applies to the OCR confidence of every value of document types without their own.
+  pub ocr_confidence: Option<f32>,
+  /// Thresholds per document type ID.
+  pub document_types: HashMap<String, DocumentTypeThresholds>,
+}
+
+/// Thresholds of one document type, taking precedence over the general ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocumentTypeThresholds {
+  pub field_confidence: Option<f32>,
+  pub ocr_confidence: Option<f32>,
+  /// Confidence thresholds of single fields, keyed by field ID or field name.
+  pub fields: HashMap<String, f32>,
+}
+
+/// What happens to a document classified with a confidence below `classification_confidence`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowConfidenceClassification {
+  /// Send the classification to validation and extract the document type it is validated as.
+  Validate,
+  /// Skip extracting the uncertain document types and copy the document to the `unclassified/` output folder.
+  #[default]
+  Unclassified,
+}
+
+impl FromStr for LowConfidenceClassification {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.to_lowercase().as_str() {
+      "validate" => Ok(LowConfidenceClassification::Validate),
+      "unclassified" => Ok(LowConfidenceClassification::Unclassified),
+      _ => Err(format!("unknown action '{}', expected validate or unclassified", value)),
+    }
+  }
+}
+
+/// An extracted field with a value below its threshold.
+#[derive(Debug, Clone, Serialize)]
+pub struct LowConfidenceField {
+  pub field_id: String,
+  pub field_name: String,
+  /// Lowest confidence of the field's values.
+  pub confidence: f64,
+  pub ocr_confidence: f64,
+  /// The threshold `confidence` is below, if it is.
+  pub confidence_threshold: Option<f32>,
+  /// The threshold `ocr_confidence` is below, if it is.
+  pub ocr_confidence_threshold: Option<f32>,
+}
+
+impl Thresholds {
+  /// Whether any threshold applies to extracted values, i.e. whether extractions are routed to validation by
+  /// confidence rather than all being validated.
+  pub fn routes_extraction(&self) -> bool {
+    self.field_confidence.is_some()
+      || self.ocr_confidence.is_some()
+      || self.document_types.values().any(|thresholds| {
+        thresholds.field_confidence.is_some() || thresholds.ocr_confidence.is_some() || !thresholds.fields.is_empty()
+      })
+  }
+
+  /// Threshold of `field` of `document_type_id`: its own, else its document type's, else the general one.
+  pub fn field_confidence(&self, document_type_id: &str, field: &Field) -> Option<f32> {
+    self.confidence_of(document_type_id, &field.field_id, &field.field_name)
+  }
+
+  /// Threshold of the rows of `table` of `document_type_id`, looked up like a field's.
+  pub fn table_confidence(&self, document_type_id: &str, table: &Table) -> Option<f32> {
+    self.confidence_of(document_type_id, &table.field_id, &table.field_name)
+  }
+
+  fn confidence_of(&self, document_type_id: &str, field_id: &str, field_name: &str) -> Option<f32> {
+    let document_type = self.document_types.get(document_type_id);
+    document_type
+      .and_then(|thresholds| {
+        thresholds
+          .fields
+          .get(field_id)
+          .or_else(|| thresholds.fields.get(field_name))
+          .copied()
+          .or(thresholds.field_confidence)
+      })
+      .or(self.field_confidence)
+  }
+
+  /// OCR confidence threshold of the values of `document_type_id`.
+  pub fn ocr_confidence(&self, document_type_id: &str) -> Option<f32> {
+    self
+      .document_types
+      .get(document_type_id)
+      .and_then(|thresholds| thresholds.ocr_confidence)
+      .or(self.ocr_confidence)
+  }
+
+  /// The fields of `extraction_results` with a value whose confidence or OCR confidence is below its threshold.
+  /// Missing fields have no values and are never reported.
+  pub fn low_confidence_fields(
+    &self,
+    document_type_id: &str,
+    extraction_results: &ExtractionResults,
+  ) -> Vec<LowConfidenceField> {
+    extraction_results
+      .results_document
+      .fields
+      .iter()
+      .flatten()
+      .filter_map(|field| self.low_confidence_field(document_type_id, field))
+      .collect()
+  }
+
+  /// `field` of `document_type_id` if it has a value whose confidence or OCR confidence is below its threshold.
+  pub fn low_confidence_field(&self, document_type_id: &str, field: &Field) -> Option<LowConfidenceField> {
+    let confidence = field.values.iter().map(|value| value.confidence).reduce(f64::min)?;
+    let ocr_confidence = field.values.iter().map(|value| value.ocr_confidence).reduce(f64::min)?;
+    let confidence_threshold = below(confidence, self.field_confidence(document_type_id, field));
+    let ocr_confidence_threshold = below(ocr_confidence, self.ocr_confidence(document_type_id));
+    (confidence_threshold.is_some() || ocr_confidence_threshold.is_some()).then(|| LowConfidenceField {
+      field_id: field.field_id.clone(),
+      field_name: field.field_name.clone(),
+      confidence,
+      ocr_confidence,
+      confidence_threshold,
+      ocr_confidence_threshold,
+    })
+  }
+
+  /// Whether the rows of `table_value`, one value of `table` of `document_type_id`, have a confidence or OCR
+  /// confidence below their threshold.
+  pub fn is_low_confidence_row(&self, document_type_id: &str, table: &Table, table_value: &TableValue) -> bool {
+    below(table_value.confidence, self.table_confidence(document_type_id, table)).is_some()
+      || below(table_value.ocr_confidence, self.ocr_confidence(document_type_id)).is_some()
+  }
+}
+
+// `threshold` if `value` is below it
+fn below(value: f64, threshold: Option<f32>) -> Option<f32> {
+  threshold.filter(|threshold| value < f64::from(*threshold))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value};
+
+  use super::*;
+
+  fn value(confidence: f64, ocr_confidence: f64) -> Value {
+    json!({
+      "components": [], "value": "x", "unformatted_value": "x", "derived_fields": [],
+      "reference": { "text_start_index": 0, "text_length": 1, "tokens": [] },
+      "confidence": confidence, "operator_confirmed": false, "ocr_confidence": ocr_confidence, "text_type": "Text"
+    })
+  }
+
+  fn field(field_id: &str, values: Vec<Value>) -> Value {
+    json!({
+      "field_id": field_id, "field_name": field_id.to_uppercase(), "field_type": "Text", "is_missing": values.is_empty(),
+      "data_source": "Automatic", "values": values, "data_version": 0, "operator_confirmed": false
+    })
+  }
+
+  fn results(fields: Vec<Value>) -> ExtractionResults {
+    serde_json::from_value(json!({
+      "document_id": "doc", "results_version": 0, "extractor_payloads": null, "business_rules_results": null,
+      "results_document": {
+        "bounds": { "start_page": 0, "page_count": 1, "text_start_index": 0, "text_length": 1 },
+        "language": "eng", "document_group": "", "document_category": "", "document_type_id": "invoices",
+        "document_type_name": "Invoices", "document_type_data_version": 0, "data_version": 0,
+        "document_type_source": "Automatic",
+        "document_type_field": value(1.0, 1.0),
+        "fields": fields, "tables": null
+      }
+    }))
+    .unwrap()
+  }
+
+  fn thresholds() -> Thresholds {
+    let invoices = DocumentTypeThresholds {
+      field_confidence: Some(0.8),
+      ocr_confidence: Some(0.9),
+      fields: HashMap::from([("total".to_string(), 0.95), ("DATE".to_string(), 0.5)]),
+    };
+    Thresholds {
+      field_confidence: Some(0.7),
+      ocr_confidence: Some(0.6),
+      document_types: HashMap::from([("invoices".to_string(), invoices)]),
+      ..Thresholds::default()
+    }
+  }
+
+  fn ids(fields: &[LowConfidenceField]) -> Vec<&str> {
+    fields.iter().map(|field| field.field_id.as_str()).collect()
+  }
+
+  #[test]
+  fn field_threshold_prefers_field_then_document_type_then_general() {
+    let thresholds = thresholds();
+    let results = results(vec![
+      field("total", vec![]),
+      field("date", vec![]),
+      field("vendor", vec![]),
+    ]);
+    let fields = results.results_document.fields.as_ref().unwrap();
+    assert_eq!(thresholds.field_confidence("invoices", &fields[0]), Some(0.95));
+    // Matched by field name when the ID has no threshold
+    assert_eq!(thresholds.field_confidence("invoices", &fields[1]), Some(0.5));
+    assert_eq!(thresholds.field_confidence("invoices", &fields[2]), Some(0.8));
+    assert_eq!(thresholds.field_confidence("receipts", &fields[0]), Some(0.7));
+    assert_eq!(Thresholds::default().field_confidence("invoices", &fields[0]), None);
+  }
+
+  #[test]
+  fn ocr_threshold_prefers_document_type() {
+    let thresholds = thresholds();
+    assert_eq!(thresholds.ocr_confidence("invoices"), Some(0.9));
+    assert_eq!(thresholds.ocr_confidence("receipts"), Some(0.6));
+  }
+
+  #[test]
+  fn low_confidence_fields_use_the_lowest_value() {
+    let thresholds = thresholds();
+    let results = results(vec![
+      field("total", vec![value(0.99, 0.99), value(0.9, 0.99)]),
+      field("vendor", vec![value(0.85, 0.95)]),
+    ]);
+    let low = thresholds.low_confidence_fields("invoices", &results);
+    assert_eq!(ids(&low), ["total"]);
+    assert_eq!(low[0].confidence, 0.9);
+    assert_eq!(low[0].confidence_threshold, Some(0.95));
+    assert_eq!(low[0].ocr_confidence_threshold, None);
+  }
+
+  #[test]
+  fn low_ocr_confidence_is_reported_on_its_own() {
+    let thresholds = thresholds();
+    let results = results(vec![field("vendor", vec![value(0.99, 0.8)])]);
+    let low = thresholds.low_confidence_fields("invoices", &results);
+    assert_eq!(ids(&low), ["vendor"]);
+    assert_eq!(low[0].confidence_threshold, None);
+    assert_eq!(low[0].ocr_confidence_threshold, Some(0.9));
+  }
+
+  #[test]
+  fn missing_fields_are_never_reported() {
+    let results = results(vec![field("total", vec![])]);
+    assert!(thresholds().low_confidence_fields("invoices", &results).is_empty());
+  }
+
+  #[test]
+  fn table_rows_use_the_table_threshold_and_the_ocr_threshold() {
+    let thresholds = Thresholds {
+      document_types: HashMap::from([(
+        "invoices".to_string(),
+        DocumentTypeThresholds {
+          ocr_confidence: Some(0.9),
+          fields: HashMap::from([("Items".to_string(), 0.95)]),
+          ..DocumentTypeThresholds::default()
+        },
+      )]),
+      ..thresholds()
+    };
+    let table: Table = serde_json::from_value(json!({
+      "field_id": "items", "field_name": "Items", "is_missing": false, "data_source": "Automatic", "data_version": 0,
+      "operator_confirmed": false,
+      "values": [
+        { "operator_confirmed": false, "confidence": 0.94, "ocr_confidence": 1.0, "cells": [], "column_info": [],
+          "number_of_rows": 0 },
+        { "operator_confirmed": false, "confidence": 0.99, "ocr_confidence": 0.85, "cells": [], "column_info": [],
+          "number_of_rows": 0 },
+        { "operator_confirmed": false, "confidence": 0.99, "ocr_confidence": 0.95, "cells": [], "column_info": [],
+          "number_of_rows": 0 }
+      ]
+    }))
+    .unwrap();
+    assert_eq!(thresholds.table_confidence("invoices", &table), Some(0.95));
+    let low: Vec<bool> = table
+      .values
+      .iter()
+      .map(|table_value| thresholds.is_low_confidence_row("invoices", &table, table_value))
+      .collect();
+    assert_eq!(low, [true, true, false]);
+    // Other document types fall back to the general thresholds
+    assert!(!thresholds.is_low_confidence_row("receipts", &table, &table.values[0]));
+  }
+
+  #[test]
+  fn extraction_is_routed_only_with_value_thresholds() {
+    assert!(thresholds().routes_extraction());
+    let classification_only = Thresholds {
+      classification_confidence: Some(0.8),
+      ..Thresholds::default()
+    };
+    assert!(!classification_only.routes_extraction());
+  }
+}
@@ -1,18 +1,40 @@
 use std::time::Duration;
 
 use reqwest::{
-  header::{HeaderMap, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-  Client,
+  header::{ACCEPT, CONTENT_TYPE},
+  Method,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Error};
+use serde_json::json;
 
-use crate::{classify::ClassificationResults, extract::ExtractionResults};
+use crate::{
+  classify::ClassificationResults,
+  client::{read_json, DuClient},
+  error::DuError,
+  extract::{ExtractionResults, Reference, ResultsDocument},
+};
 
-pub struct Validate {
-  base_url: String,
-  project_id: String,
-  bearer_token: String,
+/// Where validation tasks are created in Action Center and where the documents they show are stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ValidationSettings {
+  pub action_catalog: String,
+  pub action_folder: String,
+  pub action_priority: String,
+  pub storage_bucket: String,
+  pub storage_bucket_directory: String,
+}
+
+impl Default for ValidationSettings {
+  fn default() -> Self {
+    ValidationSettings {
+      action_catalog: "default_du_actions".to_string(),
+      action_folder: "Shared".to_string(),
+      action_priority: "Medium".to_string(),
+      storage_bucket: "du_storage_bucket".to_string(),
+      storage_bucket_directory: "du_storage_bucket".to_string(),
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +43,29 @@ struct OperationResponse {
   operation_id: String,
 }
 
+/// A document the operator confirmed in a file during classification validation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatedClassification {
+  pub document_type_id: String,
+  /// First and last page of the document, counted from 1, when the validation gives its bounds.
+  pub pages: Option<(usize, usize)>,
+}
+
+// One entry of `validatedClassificationResults`, which unlike the rest of the response uses PascalCase
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ValidatedDocumentType {
+  document_type_id: String,
+  document_bounds: Option<ValidatedBounds>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ValidatedBounds {
+  start_page: usize,
+  page_count: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ValidatedResults {
   pub result: ValidationResult,
@@ -81,125 +126,6 @@ pub struct ValidatedExtractionResults {
   pub business_rules_results: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ResultsDocument {
-  pub bounds: Bounds,
-  pub language: String,
-  pub document_group: String,
-  pub document_category: String,
-  pub document_type_id: String,
-  pub document_type_name: String,
-  pub document_type_data_version: i32,
-  pub data_version: i32,
-  pub document_type_source: String,
-  pub document_type_field: DocumentTypeField,
-  pub fields: Option<Vec<Field>>,
-  pub tables: Option<Vec<Table>>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Bounds {
-  pub start_page: i32,
-  pub page_count: i32,
-  pub text_start_index: i32,
-  pub text_length: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DocumentTypeField {
-  pub components: Vec<String>,
-  pub value: String,
-  pub unformatted_value: String,
-  pub reference: Reference,
-  pub derived_fields: Vec<String>,
-  pub confidence: f64,
-  pub operator_confirmed: bool,
-  pub ocr_confidence: f64,
-  pub text_type: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Reference {
-  pub text_start_index: i32,
-  pub text_length: i32,
-  pub tokens: Vec<Token>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Token {
-  pub text_start_index: i32,
-  pub text_length: i32,
-  pub page: i32,
-  pub page_width: f64,
-  pub page_height: f64,
-  pub boxes: Vec<Vec<f64>>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Field {
-  pub field_id: String,
-  pub field_name: String,
-  pub field_type: String,
-  pub is_missing: bool,
-  pub data_source: String,
-  pub values: Vec<Value>,
-  pub data_version: i32,
-  pub operator_confirmed: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Value {
-  pub components: Vec<String>,
-  pub value: String,
-  pub unformatted_value: String,
-  pub reference: Reference,
-  pub derived_fields: Vec<String>,
-  pub confidence: f64,
-  pub operator_confirmed: bool,
-  pub ocr_confidence: f64,
-  pub text_type: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Table {
-  pub field_id: String,
-  pub field_name: String,
-  pub is_missing: bool,
-  pub data_source: String,
-  pub data_version: i32,
-  pub operator_confirmed: bool,
-  pub values: Vec<TableValue>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TableValue {
-  pub operator_confirmed: bool,
-  pub confidence: f64,
-  pub ocr_confidence: f64,
-  pub cells: Vec<Cell>,
-  pub column_info: Vec<ColumnInfo>,
-  pub number_of_rows: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Cell {
-  pub row_index: i32,
-  pub column_index: i32,
-  pub is_header: bool,
-  pub is_missing: bool,
-  pub operator_confirmed: bool,
-  pub data_source: String,
-  pub data_version: i32,
-  pub values: Option<Vec<String>>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ColumnInfo {
-  pub field_id: String,
-  pub field_name: String,
-  pub field_type: String,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ValidateClassification {
   pub result: ClassificationResult,
@@ -240,327 +166,222 @@ pub struct DocumentBounds {
   pub text_length: usize,
 }
 
-impl Validate {
-  pub fn new(base_url: &str, project_id: &str, bearer_token: &str) -> Self {
-    Validate {
-      base_url: base_url.to_string(),
-      project_id: project_id.to_string(),
-      bearer_token: bearer_token.to_string(),
-    }
-  }
-
-  pub async fn validate_extraction_results(
+impl DuClient {
+  pub async fn validate_extraction(
     &self,
-    document_type_id: &str,
+    extractor_id: &str,
     document_id: &str,
     extraction_results: &ExtractionResults,
-  ) -> Option<ValidatedResults> {
-    let client = Client::new();
-
-    let api_url = format!(
-      "{}/{}/extractors/{}/validation/start?api-version=1",
-      self.base_url, self.project_id, document_type_id
-    );
-
-    let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, format!("Bearer {}", self.bearer_token).parse().unwrap());
-    headers.insert(ACCEPT, "application/json".parse().unwrap());
-    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+  ) -> Result<ValidatedResults, DuError> {
+    let api_url = self.project_url(&format!("extractors/{}/validation/start", extractor_id));
+    let document_type_id = &extraction_results.results_document.document_type_id;
+    let settings = &self.validation;
 
     let payload = json!({
         "documentId": document_id,
         "actionTitle": format!("Validate - {}", document_type_id),
-        "actionPriority": "Medium",
-        "actionCatalog": "default_du_actions",
-        "actionFolder": "Shared",
-        "storageBucketName": "du_storage_bucket",
-        "storageBucketDirectoryPath": "du_storage_bucket",
-        "extractionResult": extraction_results.clone(),
+        "actionPriority": settings.action_priority,
+        "actionCatalog": settings.action_catalog,
+        "actionFolder": settings.action_folder,
+        "storageBucketName": settings.storage_bucket,
+        "storageBucketDirectoryPath": settings.storage_bucket_directory,
+        "extractionResult": extraction_results,
     });
 
-    match client.post(&api_url).headers(headers).json(&payload).send().await {
-      Ok(response) => {
-        if response.status().is_success() {
-          println!("Extraction Validation request sent!");
-          let response_json: OperationResponse = response.json().await.ok()?;
-          let operation_id = response_json.operation_id;
-          Some(operation_id.clone());
-          return self
-            .submit_extraction_validation_request(&document_type_id, &operation_id)
-            .await;
-        } else {
-          println!(
-            "Error: {} - {}",
-            response.status(),
-            response.text().await.unwrap_or_default()
-          );
-        }
-      }
-      Err(err) => println!("An error occurred during validation: {}", err),
-    }
-    None
-  }
-
-  async fn submit_extraction_validation_request(
-    &self,
-    document_type_id: &str,
-    operation_id: &str,
-  ) -> Option<ValidatedResults> {
-    let url = format!(
-      "{}/{}/extractors/{}/validation/result/{}?api-version=1",
-      self.base_url, self.project_id, document_type_id, operation_id
-    );
-
-    let client = Client::new();
-
-    loop {
-      match client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", self.bearer_token))
-        .send()
-        .await
-      {
-        Ok(response) => {
-          let response_data: serde_json::Value = match response.json().await {
-            Ok(data) => data,
-            Err(err) => {
-              eprintln!("Error parsing JSON response: {}", err);
-              return None;
-            }
-          };
-
-          match response_data["status"].as_str() {
-            Some("Succeeded") => {
-              println!("Extraction Validation request submitted successfully!");
-              loop {
-                match client
-                  .get(&url)
-                  .header("Authorization", format!("Bearer {}", self.bearer_token))
-                  .send()
-                  .await
-                {
-                  Ok(response) => {
-                    let response_data: serde_json::Value = match response.json().await {
-                      Ok(data) => data,
-                      Err(err) => {
-                        eprintln!("Error parsing JSON response: {}", err);
-                        return None;
-                      }
-                    };
-
-                    match response_data["result"]["actionData"]["status"].as_str() {
-                      Some("Unassigned") => {
-                        println!("Validation Document Extraction is unassigned. Waiting...")
-                      }
-                      Some("Pending") => {
-                        println!("Validate Document Extraction in progress. Waiting...")
-                      }
-                      Some("Completed") => {
-                        println!("Validate Document Extraction is completed.");
-                        let validated_results: Result<ValidatedResults, _> =
-                          serde_json::from_value(response_data.clone());
-                        match validated_results {
-                          Ok(validated_results) => {
-                            return Some(validated_results);
-                          }
-                          _ => {}
-                        }
-                      }
-                      Some(status) => println!("Unknown validation action status: {}", status),
-                      None => {
-                        println!("No status found in actionData");
-                        return None;
-                      }
-                    }
-                    tokio::time::sleep(Duration::from_secs(5)).await; // Wait for 5 seconds before
-                                                                      // checking again
-                  }
-                  Err(err) => {
-                    eprintln!("Error sending request: {}", err);
-                    return None;
-                  }
-                }
-              }
-            }
-            Some("NotStarted") | Some("Running") | Some("Unassigned") => {
-              println!("Validation request status: {}", response_data["status"])
-            }
-            Some(status) => println!("Unknown validation request status: {}", status),
-            None => {
-              println!("No status found in response");
-              return None;
-            }
+    let response = self
+      .send(Method::POST, &api_url, |request| {
+        request
+          .header(ACCEPT, "application/json")
+          .header(CONTENT_TYPE, "application/json")
+          .json(&payload)
+      })
+      .await?;
+    let operation: OperationResponse = read_json(response).await?;
+    eprintln!("[{}] Extraction Validation request sent!", document_id);
+
+    let result_url = self.project_url(&format!(
+      "extractors/{}/validation/result/{}",
+      extractor_id, operation.operation_id
+    ));
+    let response_data = self
+      .wait_for_validation(document_id, &result_url, |response_data| {
+        match response_data["result"]["actionData"]["status"].as_str() {
+          Some("Unassigned") => eprintln!(
+            "[{}] Validation Document Extraction is unassigned. Waiting...",
+            document_id
+          ),
+          Some("Pending") => eprintln!("[{}] Validate Document Extraction in progress. Waiting...", document_id),
+          Some("Completed") => {
+            eprintln!("[{}] Validate Document Extraction is completed.", document_id);
+            return Ok(true);
+          }
+          Some(status) => eprintln!("[{}] Unknown validation action status: {}", document_id, status),
+          None => {
+            return Err(DuError::UnexpectedResponse(
+              "no status found in validation actionData".to_string(),
+            ))
           }
-          tokio::time::sleep(Duration::from_secs(5)).await; // Wait for 5 seconds before checking again
-        }
-        Err(err) => {
-          eprintln!("Error sending request: {}", err);
-          return None;
         }
-      }
-    }
+        Ok(false)
+      })
+      .await?;
+
+    serde_json::from_value(response_data.clone()).map_err(|source| DuError::Deserialize {
+      source,
+      body: response_data.to_string(),
+    })
   }
 
-  pub async fn validate_classification_results(
+  pub async fn validate_classification(
     &self,
+    classifier: &str,
     document_id: &str,
     classification_results: &ClassificationResults,
-  ) -> Option<String> {
-    let client = Client::new();
-
-    let api_url = format!(
-      "{}/{}/classifiers/ml-classification/validation/start?api-version=1",
-      self.base_url, self.project_id
-    );
+  ) -> Result<Vec<ValidatedClassification>, DuError> {
+    let api_url = self.project_url(&format!("classifiers/{}/validation/start", classifier));
 
     let document_type_id = classification_results
       .classification_results
-      .get(0) // Get the first element
+      .first()
       .map(|result| result.document_type_id.clone());
 
     let action_title = match document_type_id {
       Some(id) => format!("Validate - {}", id),
       None => "Validate - Unknown".to_string(),
     };
+    let settings = &self.validation;
 
-    let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, format!("Bearer {}", self.bearer_token).parse().unwrap());
-    headers.insert(ACCEPT, "text/plain".parse().unwrap());
-    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
-
-    let payload = serde_json::json!({
+    let payload = json!({
         "documentId": document_id,
         "actionTitle": action_title,
-        "actionPriority": "Medium",
-        "actionCatalog": "default_du_actions",
-        "actionFolder": "Shared",
-        "storageBucketName": "du_storage_bucket",
-        "storageBucketDirectoryPath": "du_storage_bucket",
+        "actionPriority": settings.action_priority,
+        "actionCatalog": settings.action_catalog,
+        "actionFolder": settings.action_folder,
+        "storageBucketName": settings.storage_bucket,
+        "storageBucketDirectoryPath": settings.storage_bucket_directory,
         "classificationResults": classification_results,
     });
 
-    match client.post(&api_url).headers(headers).json(&payload).send().await {
-      Ok(response) => {
-        if response.status().is_success() {
-          println!("Classification Validation request sent!");
-          let response_json: OperationResponse = response.json().await.ok()?;
-          let operation_id = response_json.operation_id;
-          Some(operation_id.clone());
-          return self.submit_classification_validation_request(&operation_id).await;
-        } else {
-          println!(
-            "Error: {} - {}",
-            response.status(),
-            response.text().await.unwrap_or_default()
-          );
-        }
-      }
-      Err(err) => println!("An error occurred during validation: {}", err),
-    }
-    None
-  }
-
-  async fn submit_classification_validation_request(&self, operation_id: &str) -> Option<String> {
-    let api_url = format!(
-      "{}/{}/classifiers/ml-classification/validation/result/{}?api-version=1",
-      self.base_url, self.project_id, operation_id
+    let response = self
+      .send(Method::POST, &api_url, |request| {
+        request
+          .header(ACCEPT, "text/plain")
+          .header(CONTENT_TYPE, "application/json")
+          .json(&payload)
+      })
+      .await?;
+    let operation: OperationResponse = read_json(response).await?;
+    eprintln!("[{}] Classification Validation request sent!", document_id);
+
+    let result_url = self.project_url(&format!(
+      "classifiers/{}/validation/result/{}",
+      classifier, operation.operation_id
+    ));
+    let response_data = self
+      .wait_for_validation(document_id, &result_url, |response_data| {
+        Ok(document_type_id_of(response_data).is_some())
+      })
+      .await?;
+
+    eprintln!(
+      "[{}] Classification Validation request submitted successfully!",
+      document_id
     );
-    let client = Client::new();
+    validated_classifications(&response_data)
+  }
 
+  // Polls a validation result endpoint every 5 seconds until the operation has succeeded and `is_complete` accepts
+  // the response
+  async fn wait_for_validation(
+    &self,
+    document_id: &str,
+    url: &str,
+    is_complete: impl Fn(&serde_json::Value) -> Result<bool, DuError>,
+  ) -> Result<serde_json::Value, DuError> {
     loop {
-      match client
-        .get(&api_url)
-        .header("Authorization", format!("Bearer {}", self.bearer_token))
-        .send()
-        .await
-      {
-        Ok(response) => {
-          let response_data: serde_json::Value = match response.json().await {
-            Ok(data) => data,
-            Err(err) => {
-              eprintln!("Error parsing JSON response: {}", err);
-              return None;
-            }
-          };
-
-          match response_data["status"].as_str() {
-            Some("Succeeded") => {
-              println!("Classification Validation request submitted successfully!");
-              if let Some(document_type_id) = self.get_document_type_id(&operation_id).await {
-                return Some(document_type_id);
-              }
-            }
-            Some("NotStarted") | Some("Running") | Some("Unassigned") => {
-              println!("Validation request status: {}", response_data["status"])
-            }
-            Some(status) => println!("Unknown validation request status: {}", status),
-            None => {
-              println!("No status found in response");
-              return None;
-            }
+      // The token is fetched on every poll so long-running validations survive token expiry
+      let response = self.send(Method::GET, url, |request| request).await?;
+      let response_data: serde_json::Value = read_json(response).await?;
+
+      match response_data["status"].as_str() {
+        Some("Succeeded") => {
+          if is_complete(&response_data)? {
+            return Ok(response_data);
           }
-          tokio::time::sleep(Duration::from_secs(5)).await; // Wait for 5 seconds before checking again
         }
-        Err(err) => {
-          eprintln!("Error sending request: {}", err);
-          return None;
+        Some("NotStarted") | Some("Running") | Some("Unassigned") => {
+          eprintln!(
+            "[{}] Validation request status: {}",
+            document_id, response_data["status"]
+          )
+        }
+        Some("Failed") => {
+          return Err(DuError::UnexpectedResponse(format!(
+            "validation request failed: {}",
+            response_data["error"]
+          )))
+        }
+        Some(status) => eprintln!("[{}] Unknown validation request status: {}", document_id, status),
+        None => {
+          return Err(DuError::UnexpectedResponse(
+            "no status found in validation response".to_string(),
+          ))
         }
       }
+      tokio::time::sleep(Duration::from_secs(5)).await; // Wait for 5 seconds before checking again
     }
   }
+}
 
-  async fn get_document_type_id(&self, operation_id: &str) -> Option<String> {
-    let api_url = format!(
-      "{}/{}/classifiers/ml-classification/validation/result/{}?api-version=1",
-      self.base_url, self.project_id, operation_id
-    );
-
-    let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, format!("Bearer {}", self.bearer_token).parse().unwrap());
-    headers.insert(ACCEPT, "text/plain".parse().unwrap());
-    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+fn document_type_id_of(response_data: &serde_json::Value) -> Option<&str> {
+  response_data["result"]["validatedClassificationResults"][0]["DocumentTypeId"].as_str()
+}
 
-    loop {
-      let mut request = Client::new().get(&api_url);
-      for (name, value) in &headers {
-        request = request.header(name.clone(), value.clone());
-      }
+// Every document of the validated classification, in the order the operator left them
+fn validated_classifications(response_data: &serde_json::Value) -> Result<Vec<ValidatedClassification>, DuError> {
+  let results = &response_data["result"]["validatedClassificationResults"];
+  let document_types: Vec<ValidatedDocumentType> =
+    serde_json::from_value(results.clone()).map_err(|source| DuError::Deserialize {
+      source,
+      body: results.to_string(),
+    })?;
+  Ok(
+    document_types
+      .into_iter()
+      .map(|document_type| ValidatedClassification {
+        document_type_id: document_type.document_type_id,
+        pages: document_type
+          .document_bounds
+          .map(|bounds| (bounds.start_page + 1, bounds.start_page + bounds.page_count.max(1))),
+      })
+      .collect(),
+  )
+}
 
-      match request.send().await {
-        Ok(response) => {
-          let response_data: serde_json::Value = match response.json().await {
-            Ok(data) => data,
-            Err(err) => {
-              eprintln!("Error parsing JSON response: {}", err);
-              return None;
-            }
-          };
-
-          match response_data["status"].as_str() {
-            Some("Succeeded") => {
-              println!("Classification Validation request submitted successfully!");
-              if let Some(document_type_id) =
-                response_data["result"]["validatedClassificationResults"][0]["DocumentTypeId"].as_str()
-              {
-                return Some(document_type_id.to_string());
-              }
-            }
-            Some("NotStarted") | Some("Running") | Some("Unassigned") => {
-              println!("Validation request status: {}", response_data["status"])
-            }
-            Some(status) => println!("Unknown validation request status: {}", status),
-            None => {
-              println!("No status found in response");
-              return None;
-            }
-          }
-          tokio::time::sleep(Duration::from_secs(5)).await; // Wait for 5 seconds before checking again
-        }
-        Err(err) => {
-          eprintln!("Error sending request: {}", err);
-          return None;
-        }
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_validated_document_is_returned_with_its_pages() {
+    let response = json!({
+      "status": "Succeeded",
+      "result": {
+        "validatedClassificationResults": [
+          { "DocumentTypeId": "invoices", "DocumentBounds": { "StartPage": 0, "PageCount": 2, "TextStartIndex": 0 } },
+          { "DocumentTypeId": "receipts", "DocumentBounds": { "StartPage": 2, "PageCount": 1, "TextStartIndex": 9 } },
+          { "DocumentTypeId": "other" }
+        ]
       }
-    }
+    });
+    let validated = validated_classifications(&response).unwrap();
+    let documents: Vec<(&str, Option<(usize, usize)>)> = validated
+      .iter()
+      .map(|validated| (validated.document_type_id.as_str(), validated.pages))
+      .collect();
+    assert_eq!(
+      documents,
+      [("invoices", Some((1, 2))), ("receipts", Some((3, 3))), ("other", None)]
+    );
   }
 }
@@ -0,0 +1,147 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  path::{Path, PathBuf},
+  time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+  documents::{self, DocumentFilter},
+  error::DuError,
+  pipeline::Pipeline,
+};
+
+/// Subfolders of the watched folder that documents are moved to once processed; they are never scanned.
+pub const PROCESSED_DIRECTORY: &str = "processed";
+pub const FAILED_DIRECTORY: &str = "failed";
+
+// Settings of `watch`
+pub struct WatchOptions {
+  pub recursive: bool,
+  /// How often the folder is scanned for new documents.
+  pub poll_interval: Duration,
+  /// How long a file's size and modification time must stay unchanged before it counts as completely written.
+  pub settle_time: Duration,
+}
+
+// Size and modification time of a file, and since when they have not changed
+struct Observation {
+  len: u64,
+  modified: Option<SystemTime>,
+  unchanged_since: Instant,
+}
+
+/// Scans `folder` every poll interval and runs every new document through `pipeline` once it has stopped changing,
+/// then moves it to `processed/` or `failed/` below `folder`, keeping its subfolder. Runs until Ctrl+C is pressed.
+pub async fn watch(
+  pipeline: &Pipeline,
+  folder: &Path,
+  filter: &DocumentFilter,
+  options: &WatchOptions,
+) -> Result<(), DuError> {
+  let mut observations: HashMap<PathBuf, Observation> = HashMap::new();
+  // Created once so a Ctrl+C is noticed while documents are being processed too; documents interrupted that way stay
+  // in place and resume from the manifest on the next start
+  let stop = tokio::signal::ctrl_c();
+  tokio::pin!(stop);
+  println!("Watching {} for new documents, press Ctrl+C to stop", folder.display());
+
+  loop {
+    let documents = documents::collect_documents(folder, options.recursive, filter)?;
+    let now = Instant::now();
+
+    // Forget files that disappeared, e.g. moved away by the user
+    let present: HashSet<&PathBuf> = documents.iter().collect();
+    observations.retain(|path, _| present.contains(path));
+
+    let mut ready = Vec::new();
+    for path in &documents {
+      // The file may vanish or be locked between listing and inspecting it; look again on the next scan
+      let Ok(metadata) = fs::metadata(path) else {
+        continue;
+      };
+      let (len, modified) = (metadata.len(), metadata.modified().ok());
+      match observations.get_mut(path) {
+        Some(observation) if observation.len == len && observation.modified == modified => {
+          if now.duration_since(observation.unchanged_since) >= options.settle_time {
+            ready.push(path.clone());
+          }
+        }
+        _ => {
+          observations.insert(
+            path.clone(),
+            Observation {
+              len,
+              modified,
+              unchanged_since: now,
+            },
+          );
+        }
+      }
+    }
+
+    if !ready.is_empty() {
+      let outcomes = tokio::select! {
+        _ = &mut stop => break,
+        outcomes = pipeline.run(ready.clone()) => outcomes,
+      };
+      let succeeded: HashSet<&PathBuf> = outcomes
+        .iter()
+        .filter(|(_, result)| result.is_ok())
+        .map(|(path, _)| path)
+        .collect();
+      for path in &ready {
+        let destination = if succeeded.contains(path) {
+          PROCESSED_DIRECTORY
+        } else {
+          FAILED_DIRECTORY
+        };
+        match move_document(folder, path, destination) {
+          Ok(moved_to) => println!("[{}] Moved to {}", path.display(), moved_to.display()),
+          Err(err) => eprintln!(
+            "[{}] Could not move document to {}: {}",
+            path.display(),
+            destination,
+            err
+          ),
+        }
+        observations.remove(path);
+      }
+    }
+
+    tokio::select! {
+      _ = &mut stop => break,
+      _ = tokio::time::sleep(options.poll_interval) => {}
+    }
+  }
+
+  println!("Stopped watching {}", folder.display());
+  Ok(())
+}
+
+// Moves `path` to the same subfolder of `folder/destination`, adding a number to the file name if a file with that
+// name was moved there before
+fn move_document(folder: &Path, path: &Path, destination: &str) -> Result<PathBuf, DuError> {
+  let relative_path = path.strip_prefix(folder).unwrap_or(path);
+  let mut target = folder.join(destination).join(relative_path);
+  if let Some(parent) = target.parent() {
+    fs::create_dir_all(parent)?;
+  }
+
+  let stem = target.file_stem().unwrap_or_default().to_string_lossy().to_string();
+  let extension = target
+    .extension()
+    .map(|extension| extension.to_string_lossy().to_string());
+  let mut counter = 1;
+  while target.exists() {
+    let file_name = match &extension {
+      Some(extension) => format!("{}_{}.{}", stem, counter, extension),
+      None => format!("{}_{}", stem, counter),
+    };
+    target.set_file_name(file_name);
+    counter += 1;
+  }
+
+  fs::rename(path, &target)?;
+  Ok(target)
+}